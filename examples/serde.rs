@@ -1,6 +1,6 @@
 use serde::Serialize;
 use simple_text_template::context::serde::ToContext;
-use simple_text_template::render_to_string;
+use simple_text_template::template::Template;
 use std::collections::HashMap;
 
 #[derive(Serialize, Debug, Clone)]
@@ -28,8 +28,11 @@ impl Default for Context<'static> {
 
 fn main() {
   let context = Context::default().to_context().unwrap();
-  let text = "";
-  let rendered = render_to_string(&context, text).unwrap();
+  let template = Template::parse(
+    "$item\n$for item in items: $item\n$for name, url in object:\n- [$name]($url)\n$end",
+  )
+  .unwrap();
+  let rendered = template.render_to_string(&context).unwrap();
 
   println!("{rendered}");
 }
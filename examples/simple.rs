@@ -9,7 +9,6 @@ fn main() {
     .set_value("value", "Value")
     .set_value("true", true)
     .set_value("false", false)
-    .set_list("nums", ["2", "3", "4"])
     .set_list("list", ["First", "Second", "Third"])
     .set_value("nested", vec![
       ContextBuilder::new().set_value("a", vec![
@@ -33,13 +32,13 @@ $if false:
 You wont see this
 $end
 
-$for nested in nested: $for a in nested.a: $for b in a: $for c in b: $c
+$for nested in nested: $for a in nested.a: $for b in a.b: $b.c
 
 $for val in list:
 $val
 $end
-1 $for n in nums: $n  $end 5 6
-1 $for n in nums: $n
+1 $for n in 2..5: $n  $end 5 6
+1 $for n in range(2, 5): $n
 "#;
 
   render_to_writer(context, text, stdout()).unwrap();
@@ -1,7 +1,10 @@
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
 
-#[cfg(feature = "serde_json")]
-pub use crate::context::serde_json::*;
+use itertools::Itertools;
+
+use crate::path::{self, Segment};
 
 #[derive(Debug, Clone, Default)]
 pub struct Context {
@@ -27,20 +30,65 @@ pub trait GetContentsMut {
 }
 
 pub trait GetValue: GetContents {
+  /// Gets a variable by its name, which is where every path starts
+  fn get_variable(&self, name: &str) -> Option<&Value> {
+    self.contents().get(name)
+  }
+
+  /// Gets a value by its path, like `items[0].name`, `items[-1]`, `map["key with spaces"]`
+  /// or `map[other.var]` where the key or index is the value of `other.var`
   fn get_value<S>(&self, path: S) -> Option<&Value>
   where
     S: ToString,
   {
     let path = path.to_string();
-    let mut path = path.split('.');
-    let mut result = self.contents().get(path.next().unwrap());
-
-    for name in path {
-      result = match &result {
-        Some(Value::Object(contents)) => contents.get(name),
-        Some(_) => None,
-        value => *value,
+    // `None` until the first segment has been looked up as a variable
+    let mut result: Option<&Value> = None;
+
+    for segment in path::parse(&path)? {
+      let segment = match segment {
+        Segment::Dynamic(path) => match self.get_value(path)? {
+          Value::String(key) => Segment::Key(Cow::Borrowed(key.as_str())),
+          Value::Int(index) => Segment::Index(*index),
+          Value::UInt(index) => Segment::Index(i64::try_from(*index).ok()?),
+          _ => return None,
+        },
+        segment => segment,
       };
+
+      let contents = match result {
+        None => {
+          result = Some(match segment {
+            Segment::Key(key) => self.get_variable(&key)?,
+            Segment::Index(index) => self.get_variable(&index.to_string())?,
+            Segment::Dynamic(_) => return None,
+          });
+          continue;
+        }
+        Some(Value::Object(contents)) => contents,
+        Some(Value::List(list)) => {
+          let Segment::Index(index) = segment else {
+            return None;
+          };
+          let index = match index < 0 {
+            true => list
+              .len()
+              .checked_sub(usize::try_from(index.unsigned_abs()).ok()?)?,
+            false => usize::try_from(index).ok()?,
+          };
+
+          result = Some(list.get(index)?);
+          continue;
+        }
+        Some(_) => return None,
+      };
+
+      result = Some(match segment {
+        Segment::Key(key) => contents.get(key.as_ref())?,
+        // integer keys of objects, like the ones serialized from a map
+        Segment::Index(index) => contents.get(&index.to_string())?,
+        Segment::Dynamic(_) => return None,
+      });
     }
 
     result
@@ -66,6 +114,39 @@ pub trait GetValue: GetContents {
     }
   }
 
+  /// Gets an integer, unsigned integers that don't fit are `None`
+  fn get_i64<S>(&self, value: S) -> Option<i64>
+  where
+    S: ToString,
+  {
+    match self.get_value(value)? {
+      Value::Int(int) => Some(*int),
+      Value::UInt(uint) => i64::try_from(*uint).ok(),
+      _ => None,
+    }
+  }
+
+  /// Gets any number as a float
+  fn get_f64<S>(&self, value: S) -> Option<f64>
+  where
+    S: ToString,
+  {
+    match self.get_value(value)? {
+      Value::Int(int) => Some(*int as f64),
+      Value::UInt(uint) => Some(*uint as f64),
+      Value::Float(float) => Some(*float),
+      _ => None,
+    }
+  }
+
+  /// Whether the value exists and is null, a missing value isn't null
+  fn is_null<S>(&self, value: S) -> bool
+  where
+    S: ToString,
+  {
+    matches!(self.get_value(value), Some(Value::Null))
+  }
+
   fn get_list<S>(&self, value: S) -> Option<&Vec<Value>>
   where
     S: ToString,
@@ -160,6 +241,88 @@ impl GetValue for Context {}
 impl SetValue for HashMap<String, Value> {}
 impl GetValue for HashMap<String, Value> {}
 
+/// Variables of a block on top of the ones of the blocks it's in,
+/// so a block only holds what it sets instead of a copy of every variable
+#[derive(Debug)]
+pub struct Scope<'a> {
+  variables: HashMap<String, Value>,
+  /// variables bound to a value of a scope it's in, like the elements of a `$for`,
+  /// so they don't have to be cloned
+  borrowed: HashMap<String, &'a Value>,
+  parent: Parent<'a>,
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Parent<'a> {
+  Context(&'a Context),
+  Scope(&'a Scope<'a>),
+}
+
+impl<'a> Scope<'a> {
+  /// The outermost scope, with the variables of `context`
+  pub fn new(context: &'a Context) -> Self {
+    Self {
+      variables: HashMap::new(),
+      borrowed: HashMap::new(),
+      parent: Parent::Context(context),
+    }
+  }
+
+  /// A scope inside this one, variables set in it are gone once it's dropped
+  pub fn child(&self) -> Scope<'_> {
+    Scope {
+      variables: HashMap::new(),
+      borrowed: HashMap::new(),
+      parent: Parent::Scope(self),
+    }
+  }
+
+  /// Sets a variable like [SetValue::set_value], without cloning a borrowed value
+  pub fn bind<S>(&mut self, name: S, value: Cow<'a, Value>)
+  where
+    S: ToString,
+  {
+    match value {
+      Cow::Borrowed(value) => {
+        let name = name.to_string();
+        self.variables.remove(&name);
+        self.borrowed.insert(name, value);
+      }
+      Cow::Owned(value) => {
+        self.set_value(name, value);
+      }
+    }
+  }
+}
+
+impl GetContents for Scope<'_> {
+  /// Only the variables set in this scope, not the ones it's in or the ones bound to borrowed values
+  fn contents(&self) -> &HashMap<String, Value> {
+    &self.variables
+  }
+}
+
+impl GetContentsMut for Scope<'_> {
+  fn contents_mut(&mut self) -> &mut HashMap<String, Value> {
+    &mut self.variables
+  }
+}
+
+impl SetValue for Scope<'_> {}
+
+impl GetValue for Scope<'_> {
+  fn get_variable(&self, name: &str) -> Option<&Value> {
+    // setting a bound variable replaces it, binding it again removes the one that was set
+    let variable = self.variables.get(name);
+    let variable = variable.or_else(|| self.borrowed.get(name).copied());
+
+    variable.or_else(|| match self.parent {
+      Parent::Context(context) => context.get_variable(name),
+      Parent::Scope(scope) => scope.get_variable(name),
+    })
+  }
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct ContextBuilder {
   context: Context,
@@ -219,9 +382,13 @@ impl From<Context> for ContextBuilder {
   }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Value {
+  Null,
   Bool(bool),
+  Int(i64),
+  UInt(u64),
+  Float(f64),
   String(String),
   List(Vec<Value>),
   Object(HashMap<String, Value>),
@@ -245,6 +412,31 @@ impl From<bool> for Value {
   }
 }
 
+macro_rules! impl_from_number {
+  ($variant:ident($inner:ty): $($number:ty),+) => {
+    $(
+      impl From<$number> for Value {
+        fn from(number: $number) -> Self {
+          Self::$variant(<$inner>::from(number))
+        }
+      }
+    )+
+  };
+}
+
+impl_from_number!(Int(i64): i8, i16, i32, i64);
+impl_from_number!(UInt(u64): u8, u16, u32, u64);
+impl_from_number!(Float(f64): f32, f64);
+
+impl<T> From<Option<T>> for Value
+where
+  T: Into<Value>,
+{
+  fn from(option: Option<T>) -> Self {
+    option.map_or(Self::Null, T::into)
+  }
+}
+
 impl From<String> for Value {
   fn from(string: String) -> Self {
     Self::String(string)
@@ -280,6 +472,61 @@ impl From<HashMap<&str, Value>> for Value {
   }
 }
 
+impl Value {
+  /// Whether the value counts as true in a condition,
+  /// `null`, `false`, zero and empty strings, lists and objects don't
+  pub fn is_truthy(&self) -> bool {
+    match self {
+      Value::Null => false,
+      Value::Bool(boolean) => *boolean,
+      Value::Int(int) => *int != 0,
+      Value::UInt(uint) => *uint != 0,
+      Value::Float(float) => *float != 0.0,
+      Value::String(string) => !string.is_empty(),
+      Value::List(list) => !list.is_empty(),
+      Value::Object(object) => !object.is_empty(),
+    }
+  }
+
+  /// Name of the type of value, for errors
+  pub fn type_name(&self) -> &'static str {
+    match self {
+      Value::Null => "null",
+      Value::Bool(_) => "bool",
+      Value::Int(_) => "int",
+      Value::UInt(_) => "uint",
+      Value::Float(_) => "float",
+      Value::String(_) => "string",
+      Value::List(_) => "list",
+      Value::Object(_) => "object",
+    }
+  }
+}
+
+/// Strings are written as they are, null is empty,
+/// lists and objects are written like `[a, b]` and `{a: b}` with their keys sorted
+impl Display for Value {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Value::Null => Ok(()),
+      Value::Bool(boolean) => write!(f, "{boolean}"),
+      Value::Int(int) => write!(f, "{int}"),
+      Value::UInt(uint) => write!(f, "{uint}"),
+      Value::Float(float) => write!(f, "{float}"),
+      Value::String(string) => write!(f, "{string}"),
+      Value::List(list) => write!(f, "[{}]", list.iter().join(", ")),
+      Value::Object(object) => {
+        let entries = object
+          .iter()
+          .sorted_by(|(a, _), (b, _)| a.cmp(b))
+          .map(|(key, value)| format!("{key}: {value}"));
+
+        write!(f, "{{{}}}", entries.format(", "))
+      }
+    }
+  }
+}
+
 #[cfg(feature = "serde")]
 #[allow(unused_variables)]
 pub mod serde {
@@ -339,43 +586,43 @@ pub mod serde {
     }
 
     fn serialize_i8(self, v: i8) -> Result<Self::Ok, Self::Error> {
-      self.serialize_str(&v.to_string())
+      Ok(Value::from(v))
     }
 
     fn serialize_i16(self, v: i16) -> Result<Self::Ok, Self::Error> {
-      self.serialize_str(&v.to_string())
+      Ok(Value::from(v))
     }
 
     fn serialize_i32(self, v: i32) -> Result<Self::Ok, Self::Error> {
-      self.serialize_str(&v.to_string())
+      Ok(Value::from(v))
     }
 
     fn serialize_i64(self, v: i64) -> Result<Self::Ok, Self::Error> {
-      self.serialize_str(&v.to_string())
+      Ok(Value::from(v))
     }
 
     fn serialize_u8(self, v: u8) -> Result<Self::Ok, Self::Error> {
-      self.serialize_str(&v.to_string())
+      Ok(Value::from(v))
     }
 
     fn serialize_u16(self, v: u16) -> Result<Self::Ok, Self::Error> {
-      self.serialize_str(&v.to_string())
+      Ok(Value::from(v))
     }
 
     fn serialize_u32(self, v: u32) -> Result<Self::Ok, Self::Error> {
-      self.serialize_str(&v.to_string())
+      Ok(Value::from(v))
     }
 
     fn serialize_u64(self, v: u64) -> Result<Self::Ok, Self::Error> {
-      self.serialize_str(&v.to_string())
+      Ok(Value::from(v))
     }
 
     fn serialize_f32(self, v: f32) -> Result<Self::Ok, Self::Error> {
-      self.serialize_str(&v.to_string())
+      Ok(Value::from(v))
     }
 
     fn serialize_f64(self, v: f64) -> Result<Self::Ok, Self::Error> {
-      self.serialize_str(&v.to_string())
+      Ok(Value::from(v))
     }
 
     fn serialize_char(self, v: char) -> Result<Self::Ok, Self::Error> {
@@ -391,18 +638,18 @@ pub mod serde {
     }
 
     fn serialize_none(self) -> Result<Self::Ok, Self::Error> {
-      Ok(Value::Bool(false))
+      Ok(Value::Null)
     }
 
-    fn serialize_some<T: ?Sized>(self, value: &T) -> Result<Self::Ok, Self::Error>
+    fn serialize_some<T>(self, value: &T) -> Result<Self::Ok, Self::Error>
     where
-      T: Serialize,
+      T: ?Sized + Serialize,
     {
       value.serialize(self)
     }
 
     fn serialize_unit(self) -> Result<Self::Ok, Self::Error> {
-      Err(Error::Unsupported("unit"))
+      Ok(Value::Null)
     }
 
     fn serialize_unit_struct(self, name: &'static str) -> Result<Self::Ok, Self::Error> {
@@ -418,18 +665,18 @@ pub mod serde {
       Err(Error::Unsupported("unit variant"))
     }
 
-    fn serialize_newtype_struct<T: ?Sized>(
+    fn serialize_newtype_struct<T>(
       self,
       name: &'static str,
       value: &T,
     ) -> Result<Self::Ok, Self::Error>
     where
-      T: Serialize,
+      T: ?Sized + Serialize,
     {
       Err(Error::Unsupported("newtype struct"))
     }
 
-    fn serialize_newtype_variant<T: ?Sized>(
+    fn serialize_newtype_variant<T>(
       self,
       name: &'static str,
       variant_index: u32,
@@ -437,7 +684,7 @@ pub mod serde {
       value: &T,
     ) -> Result<Self::Ok, Self::Error>
     where
-      T: Serialize,
+      T: ?Sized + Serialize,
     {
       Err(Error::Unsupported("newtype variant"))
     }
@@ -502,9 +749,9 @@ pub mod serde {
     type Ok = Value;
     type Error = Error;
 
-    fn serialize_field<T: ?Sized>(&mut self, value: &T) -> Result<(), Self::Error>
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), Self::Error>
     where
-      T: Serialize,
+      T: ?Sized + Serialize,
     {
       Err(Error::Unsupported("enum tuple variant"))
     }
@@ -518,13 +765,9 @@ pub mod serde {
     type Ok = Value;
     type Error = Error;
 
-    fn serialize_field<T: ?Sized>(
-      &mut self,
-      key: &'static str,
-      value: &T,
-    ) -> Result<(), Self::Error>
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<(), Self::Error>
     where
-      T: Serialize,
+      T: ?Sized + Serialize,
     {
       Err(Error::Unsupported("enum struct variant"))
     }
@@ -542,9 +785,9 @@ pub mod serde {
     type Ok = Value;
     type Error = Error;
 
-    fn serialize_element<T: ?Sized>(&mut self, value: &T) -> Result<(), Self::Error>
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), Self::Error>
     where
-      T: Serialize,
+      T: ?Sized + Serialize,
     {
       let element = value.serialize(Serializer)?;
       self.elements.push(element);
@@ -561,9 +804,9 @@ pub mod serde {
     type Ok = Value;
     type Error = Error;
 
-    fn serialize_element<T: ?Sized>(&mut self, value: &T) -> Result<(), Self::Error>
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), Self::Error>
     where
-      T: Serialize,
+      T: ?Sized + Serialize,
     {
       SerializeSeq::serialize_element(self, value)
     }
@@ -577,9 +820,9 @@ pub mod serde {
     type Ok = Value;
     type Error = Error;
 
-    fn serialize_field<T: ?Sized>(&mut self, value: &T) -> Result<(), Self::Error>
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), Self::Error>
     where
-      T: Serialize,
+      T: ?Sized + Serialize,
     {
       SerializeTuple::serialize_element(self, value)
     }
@@ -598,26 +841,22 @@ pub mod serde {
     type Ok = Value;
     type Error = Error;
 
-    fn serialize_key<T: ?Sized>(&mut self, key: &T) -> Result<(), Self::Error>
+    fn serialize_key<T>(&mut self, key: &T) -> Result<(), Self::Error>
     where
-      T: Serialize,
+      T: ?Sized + Serialize,
     {
       if let Some(key) = &self.next_key {
         return Err(Error::NoValueForKey(key.clone()));
       }
 
-      match key.serialize(Serializer)? {
-        Value::String(key) => {
-          self.next_key = Some(key);
-          Ok(())
-        }
-        _ => Err(Error::KeyNotString),
-      }
+      self.next_key = Some(object_key(key.serialize(Serializer)?)?);
+
+      Ok(())
     }
 
-    fn serialize_value<T: ?Sized>(&mut self, value: &T) -> Result<(), Self::Error>
+    fn serialize_value<T>(&mut self, value: &T) -> Result<(), Self::Error>
     where
-      T: Serialize,
+      T: ?Sized + Serialize,
     {
       match &self.next_key {
         Some(key) => {
@@ -632,26 +871,17 @@ pub mod serde {
       }
     }
 
-    fn serialize_entry<K: ?Sized, V: ?Sized>(
-      &mut self,
-      key: &K,
-      value: &V,
-    ) -> Result<(), Self::Error>
+    fn serialize_entry<K, V>(&mut self, key: &K, value: &V) -> Result<(), Self::Error>
     where
-      K: Serialize,
-      V: Serialize,
+      K: ?Sized + Serialize,
+      V: ?Sized + Serialize,
     {
-      let key = key.serialize(Serializer)?;
+      let key = object_key(key.serialize(Serializer)?)?;
       let value = value.serialize(Serializer)?;
 
-      match key {
-        Value::String(key) => {
-          self.contents.insert(key, value);
+      self.contents.insert(key, value);
 
-          Ok(())
-        }
-        _ => Err(Error::KeyNotString),
-      }
+      Ok(())
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
@@ -659,17 +889,22 @@ pub mod serde {
     }
   }
 
+  /// Integer keys are allowed as well, like `HashMap<u32, _>`
+  fn object_key(key: Value) -> Result<String, Error> {
+    match key {
+      Value::String(key) => Ok(key),
+      Value::Int(_) | Value::UInt(_) => Ok(key.to_string()),
+      _ => Err(Error::KeyNotString),
+    }
+  }
+
   impl SerializeStruct for ObjectSerializer {
     type Ok = Value;
     type Error = Error;
 
-    fn serialize_field<T: ?Sized>(
-      &mut self,
-      key: &'static str,
-      value: &T,
-    ) -> Result<(), Self::Error>
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<(), Self::Error>
     where
-      T: Serialize,
+      T: ?Sized + Serialize,
     {
       SerializeMap::serialize_entry(self, key, value)
     }
@@ -725,9 +960,17 @@ pub mod serde_json {
   impl From<serde_json::Value> for Value {
     fn from(value: serde_json::Value) -> Self {
       match value {
-        serde_json::Value::Null => Self::from(false),
+        serde_json::Value::Null => Self::Null,
         serde_json::Value::Bool(boolean) => Self::from(boolean),
-        serde_json::Value::Number(number) => Self::from(number.to_string()),
+        serde_json::Value::Number(number) => {
+          if let Some(int) = number.as_i64() {
+            Self::Int(int)
+          } else if let Some(uint) = number.as_u64() {
+            Self::UInt(uint)
+          } else {
+            Self::Float(number.as_f64().unwrap_or(f64::NAN))
+          }
+        }
         serde_json::Value::String(string) => Self::from(string),
         serde_json::Value::Array(array) => Self::from(array),
         serde_json::Value::Object(object) => Self::from(object),
@@ -735,3 +978,193 @@ pub mod serde_json {
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use std::borrow::Cow;
+
+  use crate::context::{ContextBuilder, GetValue, Scope, SetValue, Value};
+
+  #[test]
+  fn numbers() {
+    let context = ContextBuilder::new()
+      .set_value("int", -3)
+      .set_value("uint", u64::MAX)
+      .set_value("float", 1.5)
+      .set_value("string", "4")
+      .build();
+
+    assert_eq!(Some(-3), context.get_i64("int"));
+    assert_eq!(None, context.get_i64("uint"));
+    assert_eq!(None, context.get_i64("string"));
+    assert_eq!(Some(-3.0), context.get_f64("int"));
+    assert_eq!(Some(u64::MAX as f64), context.get_f64("uint"));
+    assert_eq!(Some(1.5), context.get_f64("float"));
+  }
+
+  #[test]
+  fn null() {
+    let context = ContextBuilder::new()
+      .set_value("null", None::<bool>)
+      .set_value("some", Some(false))
+      .build();
+
+    assert!(context.is_null("null"));
+    assert!(!context.is_null("some"));
+    assert!(!context.is_null("missing"));
+    assert_eq!(Some(&Value::Bool(false)), context.get_value("some"));
+  }
+
+  #[test]
+  fn scopes() {
+    let context = ContextBuilder::new()
+      .set_value("a", 1)
+      .set_value("b", ContextBuilder::new().set_list("c", [2, 3]))
+      .build();
+    let mut scope = Scope::new(&context);
+    scope.set_value("a", 4);
+
+    let mut child = scope.child();
+    child.set_value("d", "c");
+
+    assert_eq!(Some(&4.into()), child.get_value("a"));
+    assert_eq!(Some(&3.into()), child.get_value("b[d][-1]"));
+    assert_eq!(None, scope.get_value("d"));
+    assert_eq!(Some(&1.into()), context.get_value("a"));
+
+    let c = context.get_value("b.c").unwrap();
+    let mut child = scope.child();
+    child.bind("e", Cow::Borrowed(c));
+    child.bind("f", Cow::Owned(5.into()));
+
+    assert_eq!(Some(&2.into()), child.get_value("e[0]"));
+    assert_eq!(Some(&5.into()), child.get_value("f"));
+
+    child.set_value("e", 6);
+    assert_eq!(Some(&6.into()), child.get_value("e"));
+
+    child.bind("e", Cow::Borrowed(c));
+    assert_eq!(Some(c), child.get_value("e"));
+  }
+
+  #[test]
+  fn paths() {
+    let context = ContextBuilder::new()
+      .set_value(
+        "items",
+        vec![
+          ContextBuilder::new()
+            .set_value("name", "a")
+            .build_to_value(),
+          ContextBuilder::new()
+            .set_value("name", "b")
+            .build_to_value(),
+        ],
+      )
+      .set_value(
+        "map",
+        ContextBuilder::new()
+          .set_value("key with spaces", 1)
+          .set_value("a.b", 2)
+          .set_value("0", 3),
+      )
+      .set_value("key", "a.b")
+      .set_value("index", 1)
+      .build();
+
+    assert_eq!(Some(&"a".into()), context.get_value("items[0].name"));
+    assert_eq!(Some(&"b".into()), context.get_value("items[-1].name"));
+    assert_eq!(Some(&"a".into()), context.get_value("items[-2].name"));
+    assert_eq!(None, context.get_value("items[2]"));
+    assert_eq!(None, context.get_value("items[-3]"));
+    assert_eq!(
+      Some(&1.into()),
+      context.get_value("map[\"key with spaces\"]")
+    );
+    assert_eq!(Some(&2.into()), context.get_value("map['a.b']"));
+    assert_eq!(Some(&3.into()), context.get_value("map[0]"));
+    assert_eq!(Some(&2.into()), context.get_value("map[key]"));
+    assert_eq!(Some(&"b".into()), context.get_value("items[index].name"));
+    assert_eq!(None, context.get_value("items[missing]"));
+    assert_eq!(None, context.get_value("items.name"));
+    assert_eq!(None, context.get_value("map[0"));
+    assert_eq!(None, context.get_value("missing.name"));
+  }
+
+  #[test]
+  fn truthy() {
+    let truthy = [
+      Value::Bool(true),
+      Value::Int(-1),
+      Value::UInt(1),
+      Value::Float(0.1),
+      "a".into(),
+      vec![Value::Null].into(),
+      ContextBuilder::new().set_bool("a", false).build_to_value(),
+    ];
+    let falsy = [
+      Value::Null,
+      Value::Bool(false),
+      Value::Int(0),
+      Value::UInt(0),
+      Value::Float(0.0),
+      "".into(),
+      Value::List(Vec::new()),
+      ContextBuilder::new().build_to_value(),
+    ];
+
+    assert!(truthy.iter().all(Value::is_truthy));
+    assert!(!falsy.iter().any(Value::is_truthy));
+  }
+
+  #[test]
+  fn display() {
+    let object = ContextBuilder::new()
+      .set_value("b", 2.5)
+      .set_list("a", [Value::Null, 1.into(), true.into()])
+      .build_to_value();
+
+    assert_eq!("{a: [, 1, true], b: 2.5}", object.to_string());
+  }
+
+  #[cfg(feature = "serde")]
+  mod serde {
+    use std::collections::HashMap;
+
+    use crate::context::{Context, GetValue, Value};
+
+    #[test]
+    fn from_serialize() {
+      let value = HashMap::from([("int", Some(-1.0)), ("none", None)]);
+      let context = Context::from_serialize(&value).unwrap();
+
+      assert_eq!(Some(&Value::Float(-1.0)), context.get_value("int"));
+      assert!(context.is_null("none"));
+
+      let keys = HashMap::from([("numbers", HashMap::from([(1u32, 2u8)]))]);
+      let context = Context::from_serialize(&keys).unwrap();
+
+      assert_eq!(Some(&Value::UInt(2)), context.get_value("numbers.1"));
+    }
+  }
+
+  #[cfg(feature = "serde_json")]
+  mod serde_json {
+    use crate::context::Value;
+
+    #[test]
+    fn from_json() {
+      let json = serde_json::json!([null, -1, 18446744073709551615u64, 0.5]);
+
+      assert_eq!(
+        Value::List(vec![
+          Value::Null,
+          Value::Int(-1),
+          Value::UInt(u64::MAX),
+          Value::Float(0.5),
+        ]),
+        Value::from(json)
+      );
+    }
+  }
+}
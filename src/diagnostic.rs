@@ -0,0 +1,97 @@
+use std::fmt::{Display, Formatter};
+
+use crate::parser::ParseError;
+use crate::renderer::Error;
+use crate::span::{Snippet, Span};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Severity {
+  /// the template still renders, but probably not how it was meant to
+  Warning,
+  /// the template fails to parse or render
+  Error,
+}
+
+impl Display for Severity {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Severity::Warning => write!(f, "warning"),
+      Severity::Error => write!(f, "error"),
+    }
+  }
+}
+
+/// A problem found while checking a template
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Diagnostic {
+  pub severity: Severity,
+  pub message: String,
+  pub snippet: Snippet,
+}
+
+impl Diagnostic {
+  pub fn error<S>(message: S, snippet: Snippet) -> Self
+  where
+    S: ToString,
+  {
+    Self {
+      severity: Severity::Error,
+      message: message.to_string(),
+      snippet,
+    }
+  }
+
+  pub fn warning<S>(message: S, snippet: Snippet) -> Self
+  where
+    S: ToString,
+  {
+    Self {
+      severity: Severity::Warning,
+      message: message.to_string(),
+      snippet,
+    }
+  }
+
+  pub fn span(&self) -> Span {
+    self.snippet.span
+  }
+}
+
+impl Display for Diagnostic {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{}: {}\n{}", self.severity, self.message, self.snippet)
+  }
+}
+
+impl From<ParseError> for Diagnostic {
+  fn from(error: ParseError) -> Self {
+    Self::error(error.kind, error.snippet)
+  }
+}
+
+impl From<Error> for Diagnostic {
+  fn from(error: Error) -> Self {
+    match error {
+      Error::VariableNotFound(name, snippet) => {
+        Self::error(format!("variable not found: {name}"), snippet)
+      }
+      Error::NotIterable(name, type_name, snippet) => {
+        Self::error(format!("can't loop over {type_name}: {name}"), snippet)
+      }
+      Error::Eval(error, snippet) => Self::error(error, snippet),
+      Error::Load(error, snippet) => Self::error(error, snippet),
+      Error::Recursive(statement, chain, snippet) => {
+        Self::error(format!("recursive {statement}: {chain}"), snippet)
+      }
+      Error::TooDeep(call, limit, snippet) => Self::error(
+        format!("{call} nested more than {limit} levels deep"),
+        snippet,
+      ),
+      Error::NoParentBlock(name, snippet) => {
+        Self::error(format!("no parent $block {name} for $super()"), snippet)
+      }
+      Error::Parse(error) => error.into(),
+      error => Self::error(error, Snippet::default()),
+    }
+  }
+}
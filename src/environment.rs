@@ -0,0 +1,653 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::{Arc, PoisonError, RwLock};
+
+use crate::context::{Context, Value};
+use crate::diagnostic::Diagnostic;
+use crate::filter::{self, Filter, Function};
+use crate::loader::{LoadError, TemplateLoader};
+use crate::parser::{Node, Parser};
+use crate::renderer::{Error, Renderer};
+use crate::template::Template;
+
+/// Custom filters, functions and macros that templates can use by name,
+/// along with the built-in filters and the loader for `$include` and `$extends`,
+/// it can be shared between threads once it's set up
+#[derive(Default)]
+pub struct Environment {
+  filters: HashMap<String, Box<dyn Filter>>,
+  functions: HashMap<String, Box<dyn Function>>,
+  loader: Option<Box<dyn TemplateLoader>>,
+  /// templates from the loader, they're only loaded and parsed once
+  templates: RwLock<HashMap<String, Arc<Template>>>,
+  /// templates by the name of each `$macro` they define
+  macros: HashMap<String, Arc<Template>>,
+  /// see [Environment::set_strict]
+  strict: bool,
+}
+
+impl Environment {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Adds a filter, replacing any filter with the same name, including built-in ones
+  pub fn add_filter<S, F>(&mut self, name: S, filter: F) -> &mut Self
+  where
+    S: ToString,
+    F: Fn(&Value, &[Value]) -> Result<Value, filter::Error> + Send + Sync + 'static,
+  {
+    self.filters.insert(name.to_string(), Box::new(filter));
+    self
+  }
+
+  /// Adds a function, replacing any function with the same name
+  pub fn add_function<S, F>(&mut self, name: S, function: F) -> &mut Self
+  where
+    S: ToString,
+    F: Fn(&[Value]) -> Result<Value, filter::Error> + Send + Sync + 'static,
+  {
+    self.functions.insert(name.to_string(), Box::new(function));
+    self
+  }
+
+  /// Makes the `$macro`s of `template` available to every template rendered with this environment,
+  /// replacing any macro with the same name, macros of the template being rendered come first
+  pub fn add_macros<T>(&mut self, template: T) -> &mut Self
+  where
+    T: Into<Arc<Template>>,
+  {
+    let template = template.into();
+
+    for node in template.nodes() {
+      if let Node::Macro { name, .. } = node {
+        self.macros.insert(name.clone(), template.clone());
+      }
+    }
+
+    self
+  }
+
+  /// Requires conditions to be real bools in every template rendered with this environment,
+  /// including the ones it loads, see [Template::strict]
+  pub fn set_strict(&mut self, strict: bool) -> &mut Self {
+    self.strict = strict;
+    self
+  }
+
+  pub fn is_strict(&self) -> bool {
+    self.strict
+  }
+
+  /// Sets where templates are loaded from, forgetting any templates loaded before
+  pub fn set_loader<L>(&mut self, loader: L) -> &mut Self
+  where
+    L: TemplateLoader + 'static,
+  {
+    self.loader = Some(Box::new(loader));
+    self
+      .templates
+      .get_mut()
+      .unwrap_or_else(PoisonError::into_inner)
+      .clear();
+    self
+  }
+
+  /// Gets a template from the loader by name, parse errors point into that template
+  pub fn get_template(&self, name: &str) -> Result<Arc<Template>, LoadError> {
+    let templates = self
+      .templates
+      .read()
+      .unwrap_or_else(PoisonError::into_inner);
+
+    if let Some(template) = templates.get(name) {
+      return Ok(template.clone());
+    }
+
+    drop(templates);
+
+    let loader = self
+      .loader
+      .as_ref()
+      .ok_or_else(|| LoadError::NoLoader(name.to_string()))?;
+    let source = loader
+      .load(name)
+      .map_err(|error| LoadError::Io(name.to_string(), error))?;
+    let template = Template::parse(source).map_err(|mut error| {
+      error.snippet = error.snippet.with_file(Some(name));
+      LoadError::Parse(error)
+    })?;
+
+    // another thread could have loaded it in the meantime, every render gets the first one
+    let template = self
+      .templates
+      .write()
+      .unwrap_or_else(PoisonError::into_inner)
+      .entry(name.to_string())
+      .or_insert_with(|| Arc::new(template.named(name)))
+      .clone();
+
+    Ok(template)
+  }
+
+  pub fn filter(&self, name: &str) -> Option<&dyn Filter> {
+    match self.filters.get(name) {
+      Some(filter) => Some(filter.as_ref()),
+      None => filter::builtin(name),
+    }
+  }
+
+  pub fn function(&self, name: &str) -> Option<&dyn Function> {
+    self.functions.get(name).map(Box::as_ref)
+  }
+
+  /// The template that defines the `$macro` called `name`, see [Environment::add_macros]
+  pub fn macro_template(&self, name: &str) -> Option<&Template> {
+    self.macros.get(name).map(Arc::as_ref)
+  }
+
+  pub fn render<W>(&self, template: &Template, context: &Context, writer: W) -> Result<(), Error>
+  where
+    W: Write,
+  {
+    Renderer::new(self, context, template, writer).render()
+  }
+
+  pub fn render_to_string(&self, template: &Template, context: &Context) -> Result<String, Error> {
+    let mut buf = Vec::<u8>::new();
+
+    self.render(template, context, &mut buf)?;
+
+    Ok(String::from_utf8(buf)?)
+  }
+
+  /// Renders a template from the loader by name, along with every template it extends
+  pub fn render_named<W>(&self, name: &str, context: &Context, writer: W) -> Result<(), Error>
+  where
+    W: Write,
+  {
+    let template = self.get_template(name).map_err(|error| match error {
+      LoadError::Parse(error) => Error::Parse(error),
+      error => Error::Template(Box::new(error)),
+    })?;
+
+    self.render(&template, context, writer)
+  }
+
+  pub fn render_named_to_string(&self, name: &str, context: &Context) -> Result<String, Error> {
+    let mut buf = Vec::<u8>::new();
+
+    self.render_named(name, context, &mut buf)?;
+
+    Ok(String::from_utf8(buf)?)
+  }
+
+  /// See [Template::check]
+  pub fn check<S>(&self, source: S, context: &Context) -> Vec<Diagnostic>
+  where
+    S: AsRef<str>,
+  {
+    let (nodes, errors) = Parser::new(source.as_ref()).parse_recover();
+    let template = Template::from_nodes(source.as_ref(), nodes);
+
+    let mut diagnostics = errors
+      .into_iter()
+      .map(Diagnostic::from)
+      .chain(self.validate(&template, context))
+      .collect::<Vec<_>>();
+
+    // offsets are only comparable within a template, so the template's own diagnostics come first,
+    // followed by the ones of each template it loads
+    diagnostics
+      .sort_by(|a, b| (&a.snippet.file, a.span().offset).cmp(&(&b.snippet.file, b.span().offset)));
+    diagnostics
+  }
+
+  /// See [Template::validate]
+  pub fn validate(&self, template: &Template, context: &Context) -> Vec<Diagnostic> {
+    Renderer::new(self, context, template, std::io::sink())
+      .check()
+      .unwrap_or_else(|error| vec![error.into()])
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::context::{ContextBuilder, Value};
+  use crate::environment::Environment;
+  use crate::filter::Error;
+  use crate::template::Template;
+
+  fn environment() -> Environment {
+    let mut environment = Environment::new();
+
+    environment
+      .add_filter("currency", |value, args| match value {
+        Value::Float(float) => Ok(format!("{}{float:.2}", args[0]).into()),
+        value => Err(Error::custom(format!("can't format {}", value.type_name()))),
+      })
+      .add_filter("upper", |value, _| Ok(format!("{value}!").into()))
+      .add_function("emoji", |args| match &args[0] {
+        Value::String(status) if status == "ok" => Ok("✅".into()),
+        _ => Ok("❌".into()),
+      });
+
+    environment
+  }
+
+  #[test]
+  fn custom_filters_and_functions() {
+    let template = Template::parse(
+      "$price|currency(\"$\") $emoji(status) $if emoji(\"ok\") == \"✅\": $name|upper",
+    )
+    .unwrap();
+    let context = ContextBuilder::new()
+      .set_value("price", 1.5)
+      .set_value("status", "down")
+      .set_value("name", "a")
+      .build();
+
+    assert_eq!(
+      "$1.50 ❌ a!",
+      environment().render_to_string(&template, &context).unwrap()
+    );
+  }
+
+  #[test]
+  fn errors() {
+    let context = ContextBuilder::new().set_value("price", "free").build();
+    let render = |source: &str| {
+      let template = Template::parse(source).unwrap();
+      environment()
+        .render_to_string(&template, &context)
+        .unwrap_err()
+        .to_string()
+    };
+
+    assert_eq!(
+      "currency: can't format string\n --> 1:1\n  |\n1 | $price|currency(\"$\")\n  | ^",
+      render("$price|currency(\"$\")")
+    );
+    assert_eq!(
+      "unknown function: nope\n --> 1:1\n  |\n1 | $nope(price)\n  | ^",
+      render("$nope(price)")
+    );
+  }
+
+  #[test]
+  fn check() {
+    let context = ContextBuilder::new().build();
+    let diagnostics = environment().check("$missing|currency(\"$\") $emoji(\"ok\")|nope", &context);
+
+    let messages = diagnostics
+      .iter()
+      .map(|diagnostic| diagnostic.message.as_str())
+      .collect::<Vec<_>>();
+
+    assert_eq!(
+      vec!["variable not found: missing", "unknown filter: nope"],
+      messages
+    );
+  }
+
+  /// An environment that loads `templates` by name
+  fn includes_environment(templates: &[(&str, &str)]) -> Environment {
+    let templates = templates
+      .iter()
+      .map(|(name, source)| (name.to_string(), source.to_string()))
+      .collect::<std::collections::HashMap<_, _>>();
+
+    let mut environment = Environment::new();
+    environment.set_loader(templates);
+    environment
+  }
+
+  #[test]
+  fn shared_between_threads() {
+    let mut environment = includes_environment(&[("page.txt", "$title|shout\n")]);
+    environment.add_filter("shout", |value, _| Ok(format!("{value}!").into()));
+
+    std::thread::scope(|scope| {
+      let renders = (0..4)
+        .map(|index| {
+          let environment = &environment;
+          scope.spawn(move || {
+            let context = ContextBuilder::new().set_value("title", index).build();
+            environment.render_named_to_string("page.txt", &context)
+          })
+        })
+        .collect::<Vec<_>>();
+
+      for (index, render) in renders.into_iter().enumerate() {
+        assert_eq!(format!("{index}!\n"), render.join().unwrap().unwrap());
+      }
+    });
+  }
+
+  mod includes {
+    use super::includes_environment as environment;
+    use super::*;
+
+    fn render(environment: &Environment, source: &str) -> Result<String, String> {
+      let context = ContextBuilder::new()
+        .set_value("title", "Report")
+        .set_list("items", ["a", "b"])
+        .build();
+
+      environment
+        .render_to_string(&Template::parse(source).unwrap(), &context)
+        .map_err(|error| error.to_string())
+    }
+
+    #[test]
+    fn include() {
+      let environment = environment(&[
+        ("header.txt", "# $title\n"),
+        ("item.txt", "- $item\n"),
+        ("footer.txt", "$include \"nested.txt\""),
+        ("nested.txt", "the end\n"),
+      ]);
+
+      assert_eq!(
+        Ok("# Report\n- a\n- b\nthe end\n".to_string()),
+        render(
+          &environment,
+          "$include \"header.txt\"\n$for item in items:\n$include \"item.txt\"\n$end\n$include \"footer.txt\"\n"
+        )
+      );
+    }
+
+    #[test]
+    fn recursive() {
+      let environment = environment(&[
+        ("a.txt", "a $include \"b.txt\""),
+        ("b.txt", "$include \"a.txt\""),
+      ]);
+
+      assert_eq!(
+        Err("recursive $include: a.txt -> b.txt -> a.txt\n --> b.txt:1:1\n  |\n1 | $include \"a.txt\"\n  | ^".to_string()),
+        render(&environment, "$include \"a.txt\"")
+      );
+    }
+
+    #[test]
+    fn strict() {
+      let mut environment = environment(&[
+        ("missing.txt", "$if missing: yes $else: no $end"),
+        ("page.txt", "$include \"missing.txt\""),
+      ]);
+      let context = ContextBuilder::new().build();
+      let template = Template::parse("$include \"missing.txt\"").unwrap();
+      let error = "expected a bool but got null\n --> missing.txt:1:1\n  |\n1 | $if missing: yes $else: no $end\n  | ^";
+
+      assert_eq!(
+        Ok("no".to_string()),
+        render(&environment, "$include \"missing.txt\"")
+      );
+      assert_eq!(
+        Err(error.to_string()),
+        environment
+          .render_to_string(&template.strict(true), &context)
+          .map_err(|error| error.to_string())
+      );
+
+      environment.set_strict(true);
+      assert_eq!(
+        Err(error.to_string()),
+        environment
+          .render_named_to_string("page.txt", &context)
+          .map_err(|error| error.to_string())
+      );
+    }
+
+    #[test]
+    fn errors_in_included() {
+      let environment = environment(&[
+        ("missing.txt", "line 1\n$missing"),
+        ("invalid.txt", "$if a\n"),
+      ]);
+
+      assert_eq!(
+        Err(
+          "variable not found: missing\n --> missing.txt:2:1\n  |\n2 | $missing\n  | ^".to_string()
+        ),
+        render(&environment, "$include \"missing.txt\"")
+      );
+      assert_eq!(
+        Err("$if is missing its `:`\n --> invalid.txt:1:1\n  |\n1 | $if a\n  | ^".to_string()),
+        render(&environment, "$include \"invalid.txt\"")
+      );
+      assert_eq!(
+        Err("can't load template nope.txt: template not found\n --> 1:3\n  |\n1 | a $include \"nope.txt\"\n  |   ^".to_string()),
+        render(&environment, "a $include \"nope.txt\"")
+      );
+      assert_eq!(
+        Err("can't load template a.txt, the environment has no template loader\n --> 1:1\n  |\n1 | $include \"a.txt\"\n  | ^".to_string()),
+        render(&Environment::new(), "$include \"a.txt\"")
+      );
+    }
+
+    #[test]
+    fn check() {
+      let environment = environment(&[("a.txt", "$a\n$include \"b.txt\"\n$d\n"), ("b.txt", "$b")]);
+      let diagnostics = environment.check(
+        "$include \"a.txt\" $c\n$include \"b.txt\"\n$e",
+        &ContextBuilder::new().build(),
+      );
+
+      let found = diagnostics
+        .iter()
+        .map(|diagnostic| {
+          (
+            diagnostic.snippet.file.as_deref(),
+            diagnostic.message.as_str(),
+          )
+        })
+        .collect::<Vec<_>>();
+
+      assert_eq!(
+        vec![
+          (None, "variable not found: c"),
+          (None, "variable not found: e"),
+          (Some("a.txt"), "variable not found: a"),
+          (Some("a.txt"), "variable not found: d"),
+          (Some("b.txt"), "variable not found: b"),
+          (Some("b.txt"), "variable not found: b"),
+        ],
+        found
+      );
+    }
+  }
+
+  mod inheritance {
+    use super::includes_environment as environment;
+    use super::*;
+
+    const BASE: &str =
+      "# $block title: Report\n$block body:\nnothing here\n$end\n$block footer: the end\n";
+
+    fn render(environment: &Environment, name: &str) -> Result<String, String> {
+      let context = ContextBuilder::new()
+        .set_value("title", "Items")
+        .set_list("items", ["a", "b"])
+        .build();
+
+      environment
+        .render_named_to_string(name, &context)
+        .map_err(|error| error.to_string())
+    }
+
+    #[test]
+    fn extends() {
+      let environment = environment(&[
+        ("base.txt", BASE),
+        (
+          "child.txt",
+          "$extends \"base.txt\"\n$block title: $title $end\nignored\n",
+        ),
+        (
+          "grandchild.txt",
+          "$extends \"child.txt\"\n$block body:\n$for item in items:\n- $item\n$end\n$end\n",
+        ),
+      ]);
+
+      assert_eq!(
+        Ok("# Report\nnothing here\nthe end\n".to_string()),
+        render(&environment, "base.txt")
+      );
+      assert_eq!(
+        Ok("# Items\nnothing here\nthe end\n".to_string()),
+        render(&environment, "child.txt")
+      );
+      assert_eq!(
+        Ok("# Items\n- a\n- b\nthe end\n".to_string()),
+        render(&environment, "grandchild.txt")
+      );
+    }
+
+    #[test]
+    fn super_block() {
+      let environment = environment(&[
+        ("base.txt", BASE),
+        (
+          "child.txt",
+          "$extends \"base.txt\"\n$block footer: $super()|upper, really $end\n",
+        ),
+        (
+          "grandchild.txt",
+          "$extends \"child.txt\"\n$block footer: ($super()) $end\n",
+        ),
+        (
+          "nested.txt",
+          "$extends \"base.txt\"\n$block body:\n$block inner: $super() $end\n$end\n",
+        ),
+      ]);
+
+      assert_eq!(
+        Ok("# Report\nnothing here\nTHE END, really\n".to_string()),
+        render(&environment, "child.txt")
+      );
+      assert_eq!(
+        Ok("# Report\nnothing here\n(THE END, really)\n".to_string()),
+        render(&environment, "grandchild.txt")
+      );
+      assert_eq!(
+        Err("no parent $block inner for $super()\n --> nested.txt:3:15\n  |\n3 | $block inner: $super() $end\n  |               ^".to_string()),
+        render(&environment, "nested.txt")
+      );
+    }
+
+    #[test]
+    fn with_blocks() {
+      let environment = environment(&[
+        (
+          "base.txt",
+          "$with site = \"docs\":\n$block body: $site $end\n$end\n",
+        ),
+        (
+          "child.txt",
+          "$extends \"base.txt\"\n$with unused = 1:\n$block body: [$super()] $site $end\n$end\n",
+        ),
+      ]);
+
+      assert_eq!(Ok("docs".to_string()), render(&environment, "base.txt"));
+      assert_eq!(
+        Ok("[docs] docs".to_string()),
+        render(&environment, "child.txt")
+      );
+    }
+
+    #[test]
+    fn errors() {
+      let environment = environment(&[
+        ("a.txt", "$extends \"b.txt\"\n"),
+        ("b.txt", "$extends \"a.txt\"\n"),
+        ("orphan.txt", "a\n$extends \"missing.txt\"\n"),
+        ("super.txt", "$super()"),
+      ]);
+
+      assert_eq!(
+        Err("recursive $extends: a.txt -> b.txt -> a.txt\n --> b.txt:1:1\n  |\n1 | $extends \"a.txt\"\n  | ^".to_string()),
+        render(&environment, "a.txt")
+      );
+      assert_eq!(
+        Err("can't load template missing.txt: template not found\n --> orphan.txt:2:1\n  |\n2 | $extends \"missing.txt\"\n  | ^".to_string()),
+        render(&environment, "orphan.txt")
+      );
+      assert_eq!(
+        Err("unknown function: super\n --> super.txt:1:1\n  |\n1 | $super()\n  | ^".to_string()),
+        render(&environment, "super.txt")
+      );
+      assert_eq!(
+        Err("can't load template nope.txt: template not found".to_string()),
+        render(&environment, "nope.txt")
+      );
+    }
+
+    #[test]
+    fn check() {
+      let environment = environment(&[
+        ("base.txt", "$a\n$block body: $b $end\n"),
+        ("child.txt", "$extends \"base.txt\"\n$block body: $c $end\n"),
+      ]);
+      let template = environment.get_template("child.txt").unwrap();
+      let diagnostics = environment.validate(&template, &ContextBuilder::new().build());
+
+      let found = diagnostics
+        .iter()
+        .map(|diagnostic| {
+          (
+            diagnostic.snippet.file.as_deref(),
+            diagnostic.message.as_str(),
+          )
+        })
+        .collect::<Vec<_>>();
+
+      assert_eq!(
+        vec![
+          (Some("base.txt"), "variable not found: a"),
+          (Some("child.txt"), "variable not found: c"),
+        ],
+        found
+      );
+    }
+  }
+
+  mod macros {
+    use super::includes_environment as environment;
+    use super::*;
+
+    #[test]
+    fn shared() {
+      let mut environment = environment(&[
+        (
+          "macros.txt",
+          "$macro badge(label): [$label]\n$macro hr(): ---\n",
+        ),
+        ("base.txt", "$macro hr(): ===\n$block body: $hr()\n"),
+        (
+          "child.txt",
+          "$extends \"base.txt\"\n$block body: $hr() $badge(\"b\")\n",
+        ),
+      ]);
+      let macros = environment.get_template("macros.txt").unwrap();
+      environment.add_macros(macros);
+
+      let context = ContextBuilder::new().build();
+      let render = |source: &str| {
+        environment
+          .render_to_string(&Template::parse(source).unwrap(), &context)
+          .unwrap()
+      };
+
+      assert_eq!("[a] ---", render("$badge(\"a\") $hr()"));
+      assert_eq!("[a] ***", render("$macro hr(): ***\n$badge(\"a\") $hr()"));
+      assert_eq!(
+        "=== [b]\n",
+        environment
+          .render_named_to_string("child.txt", &context)
+          .unwrap()
+      );
+    }
+  }
+}
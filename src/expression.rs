@@ -0,0 +1,966 @@
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+
+use thiserror::Error;
+
+use crate::context::{GetValue, Scope, Value};
+use crate::environment::Environment;
+use crate::filter;
+use crate::path;
+
+/// A condition like `status == "failed"` or `count > 0 and not archived`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+  /// `"string"`, `'string'`, `1`, `1.5`, `true`, `false` or `null`
+  Literal(Value),
+  /// `name` or `object.name`
+  Var(String),
+  /// `not expr` or `!expr`
+  Not(Box<Expr>),
+  /// `expr|filter` or `expr|filter(args)`
+  Filter(Box<Expr>, FilterCall),
+  /// `function(args)`
+  Call { name: String, args: Vec<Expr> },
+  /// `left op right`
+  Binary {
+    op: BinaryOp,
+    left: Box<Expr>,
+    right: Box<Expr>,
+  },
+}
+
+/// `name` or `name(args)` after a `|`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilterCall {
+  pub name: String,
+  pub args: Vec<Expr>,
+}
+
+impl FilterCall {
+  /// Evaluates the arguments and applies the filter to `value`
+  pub fn apply(&self, value: &Value, state: &State) -> Result<Value, EvalError> {
+    let filter = state
+      .environment
+      .filter(&self.name)
+      .ok_or_else(|| EvalError::UnknownFilter(self.name.clone()))?;
+
+    filter
+      .apply(value, &eval_args(&self.args, state)?)
+      .map_err(|error| EvalError::Filter(self.name.clone(), error))
+  }
+}
+
+/// Everything an expression is evaluated against
+#[derive(Copy, Clone)]
+pub struct State<'a> {
+  pub environment: &'a Environment,
+  pub context: &'a Scope<'a>,
+  /// see [Template::strict](crate::template::Template::strict)
+  pub strict: bool,
+}
+
+/// Calls a function from the environment
+pub fn call(name: &str, args: &[Expr], state: &State) -> Result<Value, EvalError> {
+  let function = state
+    .environment
+    .function(name)
+    .ok_or_else(|| EvalError::UnknownFunction(name.to_string()))?;
+
+  function
+    .call(&eval_args(args, state)?)
+    .map_err(|error| EvalError::Function(name.to_string(), error))
+}
+
+fn eval_args(args: &[Expr], state: &State) -> Result<Vec<Value>, EvalError> {
+  args.iter().map(|arg| arg.eval(state)).collect()
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum BinaryOp {
+  Eq,
+  Ne,
+  Lt,
+  Le,
+  Gt,
+  Ge,
+  And,
+  Or,
+}
+
+impl Display for BinaryOp {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    let op = match self {
+      BinaryOp::Eq => "==",
+      BinaryOp::Ne => "!=",
+      BinaryOp::Lt => "<",
+      BinaryOp::Le => "<=",
+      BinaryOp::Gt => ">",
+      BinaryOp::Ge => ">=",
+      BinaryOp::And => "and",
+      BinaryOp::Or => "or",
+    };
+
+    write!(f, "{op}")
+  }
+}
+
+/// An expression that couldn't be parsed
+#[derive(Error, Debug, Clone, PartialEq)]
+#[error("{}")]
+pub enum ExpressionError {
+  #[error("unexpected `{0}`")]
+  Unexpected(String),
+  #[error("unexpected end of expression")]
+  UnexpectedEnd,
+  #[error("string is missing its closing quote")]
+  UnclosedString,
+  #[error("invalid number `{0}`")]
+  InvalidNumber(String),
+  #[error("expression nested more than {0} levels deep")]
+  TooDeep(usize),
+}
+
+/// An expression that couldn't be evaluated
+#[derive(Error, Debug, Clone, PartialEq)]
+#[error("{}")]
+pub enum EvalError {
+  #[error("can't compare {1} {0} {2}")]
+  Compare(BinaryOp, &'static str, &'static str),
+  #[error("expected a bool but got {0}")]
+  NotBool(&'static str),
+  #[error("unknown filter: {0}")]
+  UnknownFilter(String),
+  #[error("{0}: {1}")]
+  Filter(String, filter::Error),
+  #[error("unknown function: {0}")]
+  UnknownFunction(String),
+  #[error("{0}: {1}")]
+  Function(String, filter::Error),
+  #[error("expected an integer but got {0}")]
+  NotInt(&'static str),
+  #[error("range step can't be 0")]
+  ZeroStep,
+  #[error("range bound {0} is too large, it has to fit in an i64")]
+  RangeOverflow(u64),
+  #[error("{0} takes at most {1} arguments but got {2}")]
+  TooManyArguments(String, usize, usize),
+  #[error("{0} is missing its argument {1}")]
+  MissingArgument(String, String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+  Literal(Value),
+  Name(String),
+  Op(BinaryOp),
+  Not,
+  Open,
+  Close,
+  Comma,
+  Pipe,
+}
+
+impl Expr {
+  /// Parses an expression, errors come with the byte offset into `source` they happened at
+  pub fn parse(source: &str) -> Result<Self, (ExpressionError, usize)> {
+    let tokens = tokenize(source)?;
+    let mut parser = ExprParser {
+      tokens: &tokens,
+      index: 0,
+      end: source.len(),
+      depth: 0,
+    };
+
+    let expr = parser.parse_or()?;
+
+    match parser.tokens.get(parser.index) {
+      None => Ok(expr),
+      Some((_, offset)) => Err(parser.unexpected(source, *offset)),
+    }
+  }
+
+  /// Parses comma separated arguments, like the `"a", b` in `replace("a", b)`
+  pub fn parse_args(source: &str) -> Result<Vec<Self>, (ExpressionError, usize)> {
+    let tokens = tokenize(source)?;
+    let mut parser = ExprParser {
+      tokens: &tokens,
+      index: 0,
+      end: source.len(),
+      depth: 0,
+    };
+
+    if tokens.is_empty() {
+      return Ok(Vec::new());
+    }
+
+    let args = parser.parse_list()?;
+
+    match parser.tokens.get(parser.index) {
+      None => Ok(args),
+      Some((_, offset)) => Err(parser.unexpected(source, *offset)),
+    }
+  }
+
+  /// Every variable the expression uses
+  pub fn variables(&self) -> Vec<&str> {
+    match self {
+      Expr::Literal(_) => vec![],
+      Expr::Var(name) => vec![name],
+      Expr::Not(expr) => expr.variables(),
+      Expr::Filter(expr, filter) => {
+        let mut variables = expr.variables();
+        variables.extend(filter.args.iter().flat_map(Expr::variables));
+        variables
+      }
+      Expr::Call { args, .. } => args.iter().flat_map(Expr::variables).collect(),
+      Expr::Binary { left, right, .. } => {
+        let mut variables = left.variables();
+        variables.extend(right.variables());
+        variables
+      }
+    }
+  }
+
+  /// Evaluates the expression, missing variables are `null`,
+  /// a strict `state` requires `not`, `and` and `or` to be used on real bools instead of truthy values
+  pub fn eval(&self, state: &State) -> Result<Value, EvalError> {
+    match self {
+      Expr::Literal(value) => Ok(value.clone()),
+      Expr::Var(name) => Ok(
+        state
+          .context
+          .get_value(name)
+          .cloned()
+          .unwrap_or(Value::Null),
+      ),
+      Expr::Not(expr) => Ok(Value::Bool(!expr.eval_bool(state)?)),
+      Expr::Filter(expr, filter) => filter.apply(&expr.eval(state)?, state),
+      Expr::Call { name, args } => call(name, args, state),
+      Expr::Binary { op, left, right } => {
+        let result = match op {
+          BinaryOp::And => left.eval_bool(state)? && right.eval_bool(state)?,
+          BinaryOp::Or => left.eval_bool(state)? || right.eval_bool(state)?,
+          op => compare(*op, &left.eval(state)?, &right.eval(state)?)?,
+        };
+
+        Ok(Value::Bool(result))
+      }
+    }
+  }
+
+  /// Evaluates the expression as a condition, see [Value::is_truthy],
+  /// a strict `state` requires it to be a bool
+  pub fn eval_bool(&self, state: &State) -> Result<bool, EvalError> {
+    match self.eval(state)? {
+      Value::Bool(boolean) => Ok(boolean),
+      value if state.strict => Err(EvalError::NotBool(value.type_name())),
+      value => Ok(value.is_truthy()),
+    }
+  }
+}
+
+/// `==` and `!=` work on anything, numbers of different types are compared by value,
+/// the other comparisons only work on two numbers or two strings
+fn compare(op: BinaryOp, left: &Value, right: &Value) -> Result<bool, EvalError> {
+  match (op, compare_values(left, right)) {
+    (BinaryOp::Eq, None) => Ok(left == right),
+    (BinaryOp::Ne, None) => Ok(left != right),
+    (BinaryOp::Eq, Some(ordering)) => Ok(ordering.is_eq()),
+    (BinaryOp::Ne, Some(ordering)) => Ok(ordering.is_ne()),
+    (BinaryOp::Lt, Some(ordering)) => Ok(ordering.is_lt()),
+    (BinaryOp::Le, Some(ordering)) => Ok(ordering.is_le()),
+    (BinaryOp::Gt, Some(ordering)) => Ok(ordering.is_gt()),
+    (BinaryOp::Ge, Some(ordering)) => Ok(ordering.is_ge()),
+    (op, _) => Err(EvalError::Compare(op, left.type_name(), right.type_name())),
+  }
+}
+
+/// Orders two strings or two numbers, anything else can't be ordered
+pub(crate) fn compare_values(left: &Value, right: &Value) -> Option<Ordering> {
+  match (left, right) {
+    (Value::String(left), Value::String(right)) => Some(left.cmp(right)),
+    _ => compare_numbers(left, right),
+  }
+}
+
+fn compare_numbers(left: &Value, right: &Value) -> Option<Ordering> {
+  let integer = |value: &Value| match value {
+    Value::Int(int) => Some(i128::from(*int)),
+    Value::UInt(uint) => Some(i128::from(*uint)),
+    _ => None,
+  };
+  let float = |value: &Value| match value {
+    Value::Float(float) => Some(*float),
+    value => integer(value).map(|int| int as f64),
+  };
+
+  match (integer(left), integer(right)) {
+    (Some(left), Some(right)) => Some(left.cmp(&right)),
+    _ => float(left)?.partial_cmp(&float(right)?),
+  }
+}
+
+/// How deep parentheses, arguments, operators and filters can be nested in an expression,
+/// so a huge one is an error instead of overflowing the stack when it's parsed or evaluated
+const MAX_DEPTH: usize = 64;
+
+struct ExprParser<'a> {
+  tokens: &'a [(Token, usize)],
+  index: usize,
+  /// offset of the end of the source, for errors at the end
+  end: usize,
+  /// how deep the expression being parsed is nested, up to `MAX_DEPTH`
+  depth: usize,
+}
+
+impl ExprParser<'_> {
+  fn peek(&self) -> Option<&Token> {
+    self.tokens.get(self.index).map(|(token, _)| token)
+  }
+
+  fn next(&mut self) -> Result<&(Token, usize), (ExpressionError, usize)> {
+    let token = self
+      .tokens
+      .get(self.index)
+      .ok_or((ExpressionError::UnexpectedEnd, self.end))?;
+    self.index += 1;
+
+    Ok(token)
+  }
+
+  /// Offset of the next token
+  fn offset(&self) -> usize {
+    self
+      .tokens
+      .get(self.index)
+      .map_or(self.end, |(_, offset)| *offset)
+  }
+
+  /// Goes one level deeper for the token at `offset`,
+  /// the caller goes back up once it's parsed what's nested
+  fn nest(&mut self, offset: usize) -> Result<(), (ExpressionError, usize)> {
+    self.depth += 1;
+
+    match self.depth > MAX_DEPTH {
+      true => Err((ExpressionError::TooDeep(MAX_DEPTH), offset)),
+      false => Ok(()),
+    }
+  }
+
+  fn unexpected(&self, source: &str, offset: usize) -> (ExpressionError, usize) {
+    let token = source[offset..].split_whitespace().next().unwrap_or("");
+    (ExpressionError::Unexpected(token.to_string()), offset)
+  }
+
+  fn parse_or(&mut self) -> Result<Expr, (ExpressionError, usize)> {
+    let mut expr = self.parse_and()?;
+    let depth = self.depth;
+
+    // every operator nests the expression before it one level deeper
+    while self.peek() == Some(&Token::Op(BinaryOp::Or)) {
+      self.nest(self.offset())?;
+      self.index += 1;
+      let right = self.parse_and()?;
+      expr = binary(BinaryOp::Or, expr, right);
+    }
+
+    self.depth = depth;
+    Ok(expr)
+  }
+
+  fn parse_and(&mut self) -> Result<Expr, (ExpressionError, usize)> {
+    let mut expr = self.parse_not()?;
+    let depth = self.depth;
+
+    // every operator nests the expression before it one level deeper
+    while self.peek() == Some(&Token::Op(BinaryOp::And)) {
+      self.nest(self.offset())?;
+      self.index += 1;
+      let right = self.parse_not()?;
+      expr = binary(BinaryOp::And, expr, right);
+    }
+
+    self.depth = depth;
+    Ok(expr)
+  }
+
+  fn parse_not(&mut self) -> Result<Expr, (ExpressionError, usize)> {
+    if self.peek() == Some(&Token::Not) {
+      self.nest(self.offset())?;
+      self.index += 1;
+      let expr = Expr::Not(Box::new(self.parse_not()?));
+      self.depth -= 1;
+
+      return Ok(expr);
+    }
+
+    self.parse_comparison()
+  }
+
+  /// comparisons can't be chained, `a < b < c` is an error
+  fn parse_comparison(&mut self) -> Result<Expr, (ExpressionError, usize)> {
+    let left = self.parse_filters()?;
+
+    match self.peek() {
+      Some(Token::Op(op)) if !matches!(op, BinaryOp::And | BinaryOp::Or) => {
+        let op = *op;
+        self.nest(self.offset())?;
+        self.index += 1;
+        let right = self.parse_filters()?;
+        self.depth -= 1;
+
+        Ok(binary(op, left, right))
+      }
+      _ => Ok(left),
+    }
+  }
+
+  fn parse_filters(&mut self) -> Result<Expr, (ExpressionError, usize)> {
+    let mut expr = self.parse_primary()?;
+    let depth = self.depth;
+
+    while self.peek() == Some(&Token::Pipe) {
+      self.nest(self.offset())?;
+      self.index += 1;
+
+      let name = match self.next()? {
+        (Token::Name(name), _) => name.clone(),
+        (token, offset) => return Err((ExpressionError::Unexpected(token_text(token)), *offset)),
+      };
+      let args = match self.peek() {
+        Some(Token::Open) => {
+          self.index += 1;
+          self.parse_args()?
+        }
+        _ => Vec::new(),
+      };
+
+      expr = Expr::Filter(Box::new(expr), FilterCall { name, args });
+    }
+
+    self.depth = depth;
+    Ok(expr)
+  }
+
+  /// Parses arguments up to and including the closing `)`
+  fn parse_args(&mut self) -> Result<Vec<Expr>, (ExpressionError, usize)> {
+    if self.peek() == Some(&Token::Close) {
+      self.index += 1;
+      return Ok(Vec::new());
+    }
+
+    let args = self.parse_list()?;
+
+    match self.next()? {
+      (Token::Close, _) => Ok(args),
+      (token, offset) => Err((ExpressionError::Unexpected(token_text(token)), *offset)),
+    }
+  }
+
+  fn parse_list(&mut self) -> Result<Vec<Expr>, (ExpressionError, usize)> {
+    let mut list = vec![self.parse_or()?];
+
+    while self.peek() == Some(&Token::Comma) {
+      self.index += 1;
+      list.push(self.parse_or()?);
+    }
+
+    Ok(list)
+  }
+
+  fn parse_primary(&mut self) -> Result<Expr, (ExpressionError, usize)> {
+    let (token, offset) = self.next()?.clone();
+
+    match token {
+      Token::Literal(value) => Ok(Expr::Literal(value)),
+      Token::Name(name) if self.peek() == Some(&Token::Open) => {
+        self.nest(self.offset())?;
+        self.index += 1;
+        let args = self.parse_args()?;
+        self.depth -= 1;
+
+        Ok(Expr::Call { name, args })
+      }
+      Token::Name(name) => Ok(Expr::Var(name)),
+      Token::Open => {
+        self.nest(offset)?;
+        let expr = self.parse_or()?;
+        self.depth -= 1;
+
+        match self.next()? {
+          (Token::Close, _) => Ok(expr),
+          (token, offset) => Err((ExpressionError::Unexpected(token_text(token)), *offset)),
+        }
+      }
+      token => Err((ExpressionError::Unexpected(token_text(&token)), offset)),
+    }
+  }
+}
+
+fn binary(op: BinaryOp, left: Expr, right: Expr) -> Expr {
+  Expr::Binary {
+    op,
+    left: Box::new(left),
+    right: Box::new(right),
+  }
+}
+
+fn token_text(token: &Token) -> String {
+  match token {
+    Token::Literal(value) => value.to_string(),
+    Token::Name(name) => name.clone(),
+    Token::Op(op) => op.to_string(),
+    Token::Not => "not".to_string(),
+    Token::Open => "(".to_string(),
+    Token::Close => ")".to_string(),
+    Token::Comma => ",".to_string(),
+    Token::Pipe => "|".to_string(),
+  }
+}
+
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, (ExpressionError, usize)> {
+  let mut tokens = Vec::new();
+  let mut offset = 0;
+
+  while offset < source.len() {
+    let rest = &source[offset..];
+    let start = offset;
+
+    let Some(c) = rest.chars().next() else {
+      break;
+    };
+
+    let (token, len) = match c {
+      c if c.is_whitespace() => {
+        offset += c.len_utf8();
+        continue;
+      }
+      '(' => (Token::Open, 1),
+      ')' => (Token::Close, 1),
+      '"' | '\'' => {
+        let (string, len) = split_string(rest).ok_or((ExpressionError::UnclosedString, start))?;
+        (Token::Literal(Value::String(string)), len)
+      }
+      _ if rest.starts_with("==") => (Token::Op(BinaryOp::Eq), 2),
+      _ if rest.starts_with("!=") => (Token::Op(BinaryOp::Ne), 2),
+      _ if rest.starts_with("<=") => (Token::Op(BinaryOp::Le), 2),
+      _ if rest.starts_with(">=") => (Token::Op(BinaryOp::Ge), 2),
+      '<' => (Token::Op(BinaryOp::Lt), 1),
+      '>' => (Token::Op(BinaryOp::Gt), 1),
+      '!' => (Token::Not, 1),
+      ',' => (Token::Comma, 1),
+      '|' => (Token::Pipe, 1),
+      c if c.is_ascii_digit()
+        || (c == '-' && rest[1..].starts_with(|c: char| c.is_ascii_digit())) =>
+      {
+        let len = number_len(rest);
+        let number = parse_number(&rest[..len]).ok_or_else(|| {
+          (
+            ExpressionError::InvalidNumber(rest[..len].to_string()),
+            start,
+          )
+        })?;
+        (Token::Literal(number), len)
+      }
+      c if c.is_alphabetic() || c == '_' => {
+        let name = &rest[..path::path_len(rest)];
+
+        let token = match name {
+          "and" => Token::Op(BinaryOp::And),
+          "or" => Token::Op(BinaryOp::Or),
+          "not" => Token::Not,
+          "true" => Token::Literal(Value::Bool(true)),
+          "false" => Token::Literal(Value::Bool(false)),
+          "null" => Token::Literal(Value::Null),
+          name => Token::Name(name.to_string()),
+        };
+
+        (token, name.len())
+      }
+      c => {
+        return Err((ExpressionError::Unexpected(c.to_string()), start));
+      }
+    };
+
+    tokens.push((token, start));
+    offset += len;
+  }
+
+  Ok(tokens)
+}
+
+/// Splits a quoted string off of the start of `source`, returns the unescaped string and its length,
+/// `\` escapes the quote and itself
+pub(crate) fn split_string(source: &str) -> Option<(String, usize)> {
+  let mut chars = source.char_indices();
+  let (_, quote) = chars.next()?;
+  let mut string = String::new();
+
+  while let Some((index, c)) = chars.next() {
+    match c {
+      '\\' => string.push(chars.next()?.1),
+      c if c == quote => return Some((string, index + 1)),
+      c => string.push(c),
+    }
+  }
+
+  None
+}
+
+/// Finds the first place in `source` that `stop` is true for, skipping over quoted strings,
+/// `stop` gets the rest of `source` from there and how many `(` or `[` it's inside of
+pub(crate) fn scan<F>(source: &str, mut stop: F) -> Option<usize>
+where
+  F: FnMut(&str, usize) -> bool,
+{
+  let mut depth = 0_usize;
+  let mut quote = None;
+  let mut escaped = false;
+
+  for (index, c) in source.char_indices() {
+    match (quote, c) {
+      (Some(_), _) if escaped => escaped = false,
+      (Some(_), '\\') => escaped = true,
+      (Some(q), c) if c == q => quote = None,
+      (Some(_), _) => {}
+      (None, _) if stop(&source[index..], depth) => return Some(index),
+      (None, '"' | '\'') => quote = Some(c),
+      (None, '(' | '[') => depth += 1,
+      (None, ')' | ']') => depth = depth.saturating_sub(1),
+      (None, _) => {}
+    }
+  }
+
+  None
+}
+
+/// `1.5` is a single number, but `1..5` isn't
+fn number_len(source: &str) -> usize {
+  let digits = |source: &str| {
+    source
+      .find(|c: char| !c.is_ascii_digit() && c != '_')
+      .unwrap_or(source.len())
+  };
+
+  let sign = usize::from(source.starts_with('-'));
+  let len = sign + digits(&source[sign..]);
+  let fraction = &source[len..];
+
+  match fraction.strip_prefix('.') {
+    Some(rest) if rest.starts_with(|c: char| c.is_ascii_digit()) => len + 1 + digits(rest),
+    _ => len,
+  }
+}
+
+fn parse_number(number: &str) -> Option<Value> {
+  let number = number.replace('_', "");
+
+  if number.contains('.') {
+    return number.parse().ok().map(Value::Float);
+  }
+
+  match number.parse() {
+    Ok(int) => Some(Value::Int(int)),
+    Err(_) => number.parse().ok().map(Value::UInt),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::context::{ContextBuilder, Scope, Value};
+  use crate::environment::Environment;
+  use crate::expression::{scan, BinaryOp, EvalError, Expr, ExpressionError, FilterCall, State};
+
+  fn var(name: &str) -> Box<Expr> {
+    Box::new(Expr::Var(name.to_string()))
+  }
+
+  fn literal<V>(value: V) -> Box<Expr>
+  where
+    V: Into<Value>,
+  {
+    Box::new(Expr::Literal(value.into()))
+  }
+
+  mod parse {
+    use super::*;
+
+    #[test]
+    fn literals() {
+      let parse = |source| Expr::parse(source).unwrap();
+
+      assert_eq!(*literal("a \"b\" c"), parse(r#""a \"b\" c""#));
+      assert_eq!(*literal("it's"), parse(r#"'it\'s'"#));
+      assert_eq!(*literal(-12), parse("-12"));
+      assert_eq!(*literal(1_000), parse("1_000"));
+      assert_eq!(*literal(u64::MAX), parse("18446744073709551615"));
+      assert_eq!(*literal(0.5), parse("0.5"));
+      assert_eq!(*literal(true), parse("true"));
+      assert_eq!(Expr::Literal(Value::Null), parse("null"));
+    }
+
+    #[test]
+    fn scanning() {
+      let colon = |source| scan(source, |rest, _| rest.starts_with(':'));
+      let closing = |source| scan(source, |rest, depth| depth == 0 && rest.starts_with(')'));
+
+      assert_eq!(Some(10), colon(r#"a "b:\"c" : d"#));
+      assert_eq!(Some(10), colon(r#"'it\'s :' :"#));
+      assert_eq!(None, colon(r#""a: b"#));
+      assert_eq!(Some(10), closing(r#"f(a, ")") )"#));
+    }
+
+    #[test]
+    fn precedence() {
+      assert_eq!(
+        Ok(Expr::Binary {
+          op: BinaryOp::Or,
+          left: var("a"),
+          right: Box::new(Expr::Binary {
+            op: BinaryOp::And,
+            left: Box::new(Expr::Not(Box::new(Expr::Binary {
+              op: BinaryOp::Eq,
+              left: var("b.c"),
+              right: literal("x"),
+            }))),
+            right: Box::new(Expr::Binary {
+              op: BinaryOp::Ge,
+              left: var("d"),
+              right: literal(1),
+            }),
+          }),
+        }),
+        Expr::parse("a or not b.c == \"x\" and d >= 1")
+      );
+    }
+
+    #[test]
+    fn parentheses() {
+      assert_eq!(
+        Ok(Expr::Binary {
+          op: BinaryOp::And,
+          left: Box::new(Expr::Binary {
+            op: BinaryOp::Or,
+            left: var("a"),
+            right: var("b"),
+          }),
+          right: Box::new(Expr::Not(Box::new(Expr::Not(var("c"))))),
+        }),
+        Expr::parse("(a or b) and !!c")
+      );
+    }
+
+    #[test]
+    fn filters() {
+      assert_eq!(
+        Ok(Expr::Binary {
+          op: BinaryOp::Gt,
+          left: Box::new(Expr::Filter(
+            Box::new(Expr::Filter(
+              var("items"),
+              FilterCall {
+                name: "default".to_string(),
+                args: vec![*literal(0), *literal(true)],
+              }
+            )),
+            FilterCall {
+              name: "length".to_string(),
+              args: vec![],
+            }
+          )),
+          right: literal(2),
+        }),
+        Expr::parse("items|default(0, true)|length > 2")
+      );
+    }
+
+    #[test]
+    fn calls() {
+      assert_eq!(
+        Ok(Expr::Binary {
+          op: BinaryOp::Eq,
+          left: Box::new(Expr::Call {
+            name: "status".to_string(),
+            args: vec![*var("a"), *literal(1)],
+          }),
+          right: Box::new(Expr::Call {
+            name: "none".to_string(),
+            args: vec![],
+          }),
+        }),
+        Expr::parse("status(a, 1) == none()")
+      );
+    }
+
+    #[test]
+    fn errors() {
+      assert_eq!(
+        Err((ExpressionError::UnexpectedEnd, 6)),
+        Expr::parse("a == (")
+      );
+      assert_eq!(
+        Err((ExpressionError::Unexpected("b".to_string()), 2)),
+        Expr::parse("a b")
+      );
+      assert_eq!(
+        Err((ExpressionError::Unexpected("<".to_string()), 6)),
+        Expr::parse("a < b < c")
+      );
+      assert_eq!(
+        Err((ExpressionError::UnclosedString, 5)),
+        Expr::parse("a == \"b")
+      );
+      assert_eq!(
+        Err((ExpressionError::Unexpected("&".to_string()), 2)),
+        Expr::parse("a && b")
+      );
+    }
+
+    #[test]
+    fn too_deep() {
+      let nested = |depth| format!("{}a{}", "(".repeat(depth), ")".repeat(depth));
+
+      assert!(Expr::parse(&nested(64)).is_ok());
+      assert_eq!(
+        Err((ExpressionError::TooDeep(64), 64)),
+        Expr::parse(&nested(65))
+      );
+      assert_eq!(
+        Err((ExpressionError::TooDeep(64), 64)),
+        Expr::parse(&nested(3000))
+      );
+      assert_eq!(
+        Err((ExpressionError::TooDeep(64), 322)),
+        Expr::parse(&["a"; 1000].join(" or "))
+      );
+      assert_eq!(
+        Err((ExpressionError::TooDeep(64), 64)),
+        Expr::parse(&format!("{}a", "!".repeat(1000)))
+      );
+      assert_eq!(
+        Err((ExpressionError::TooDeep(64), 385)),
+        Expr::parse(&format!("a{}", "|upper".repeat(1000)))
+      );
+    }
+  }
+
+  mod eval {
+    use super::*;
+
+    fn eval(source: &str) -> Result<bool, EvalError> {
+      eval_strict(source, false)
+    }
+
+    fn eval_strict(source: &str, strict: bool) -> Result<bool, EvalError> {
+      let context = ContextBuilder::new()
+        .set_value("status", "failed")
+        .set_value("count", 3)
+        .set_value("big", u64::MAX)
+        .set_value("half", 0.5)
+        .set_bool("archived", false)
+        .set_value("null", Value::Null)
+        .set_list("list", ["a"])
+        .build();
+
+      let state = State {
+        environment: &Environment::new(),
+        context: &Scope::new(&context),
+        strict,
+      };
+
+      Expr::parse(source).unwrap().eval_bool(&state)
+    }
+
+    #[test]
+    fn comparisons() {
+      assert_eq!(Ok(true), eval("status == \"failed\""));
+      assert_eq!(Ok(false), eval("status != 'failed'"));
+      assert_eq!(Ok(true), eval("status < \"g\""));
+      assert_eq!(Ok(true), eval("count > 0 and not archived"));
+      assert_eq!(Ok(true), eval("count == 3.0"));
+      assert_eq!(Ok(true), eval("big > count"));
+      assert_eq!(Ok(true), eval("half <= 1"));
+      assert_eq!(Ok(false), eval("count == \"3\""));
+      assert_eq!(Ok(true), eval("null == null and missing == null"));
+      assert_eq!(Ok(true), eval("archived or (count >= 3 and half < count)"));
+    }
+
+    #[test]
+    fn missing_is_false() {
+      assert_eq!(Ok(false), eval("missing"));
+      assert_eq!(Ok(true), eval("!missing"));
+    }
+
+    #[test]
+    fn type_errors() {
+      assert_eq!(
+        Err(EvalError::Compare(BinaryOp::Gt, "string", "int")),
+        eval("status > 1")
+      );
+      assert_eq!(
+        Err(EvalError::Compare(BinaryOp::Lt, "null", "int")),
+        eval("missing < 1")
+      );
+    }
+
+    #[test]
+    fn truthy() {
+      assert_eq!(Ok(true), eval("status and count and half and list"));
+      assert_eq!(Ok(false), eval("null or missing"));
+      assert_eq!(Ok(true), eval("not \"\" and not 0 and not 0.0"));
+    }
+
+    #[test]
+    fn strict() {
+      assert_eq!(Ok(true), eval_strict("count > 0 and not archived", true));
+      assert_eq!(
+        Err(EvalError::NotBool("string")),
+        eval_strict("status", true)
+      );
+      assert_eq!(
+        Err(EvalError::NotBool("list")),
+        eval_strict("archived or list", true)
+      );
+      assert_eq!(
+        Err(EvalError::NotBool("null")),
+        eval_strict("!missing", true)
+      );
+      assert_eq!(Ok(false), eval_strict("archived and status", true));
+    }
+
+    #[test]
+    fn error_messages() {
+      assert_eq!(
+        "can't compare string > int",
+        EvalError::Compare(BinaryOp::Gt, "string", "int").to_string()
+      );
+      assert_eq!(
+        "expected a bool but got string",
+        EvalError::NotBool("string").to_string()
+      );
+    }
+
+    #[test]
+    fn filters() {
+      assert_eq!(Ok(true), eval("status|upper == \"FAILED\""));
+      assert_eq!(
+        Ok(true),
+        eval("list|length == 1 and missing|default(1) == 1")
+      );
+      assert_eq!(
+        Err(EvalError::UnknownFilter("nope".to_string())),
+        eval("status|nope")
+      );
+      assert_eq!(
+        "upper: expected a string but got int",
+        eval("count|upper").unwrap_err().to_string()
+      );
+    }
+
+    #[test]
+    fn short_circuit() {
+      assert_eq!(Ok(false), eval("archived and status"));
+      assert_eq!(Ok(true), eval("count == 3 or status"));
+    }
+  }
+}
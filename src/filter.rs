@@ -0,0 +1,393 @@
+use itertools::Itertools;
+use thiserror::Error;
+
+use crate::context::Value;
+use crate::expression::compare_values;
+
+/// Transforms a value in a pipeline like `$name|upper|truncate(10)`
+pub trait Filter: Send + Sync {
+  fn apply(&self, value: &Value, args: &[Value]) -> Result<Value, Error>;
+}
+
+impl<F> Filter for F
+where
+  F: Fn(&Value, &[Value]) -> Result<Value, Error> + Send + Sync,
+{
+  fn apply(&self, value: &Value, args: &[Value]) -> Result<Value, Error> {
+    self(value, args)
+  }
+}
+
+/// Called by name in a template, like `$name(args)` or `$if name(args):`
+pub trait Function: Send + Sync {
+  fn call(&self, args: &[Value]) -> Result<Value, Error>;
+}
+
+impl<F> Function for F
+where
+  F: Fn(&[Value]) -> Result<Value, Error> + Send + Sync,
+{
+  fn call(&self, args: &[Value]) -> Result<Value, Error> {
+    self(args)
+  }
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+#[error("{}")]
+pub enum Error {
+  #[error("expected {0} but got {1}")]
+  WrongType(&'static str, &'static str),
+  #[error("expected {0} arguments but got {1}")]
+  ArgumentCount(&'static str, usize),
+  #[error("can't sort {0} and {1}")]
+  Unsortable(&'static str, &'static str),
+  #[error("{0}")]
+  Custom(String),
+}
+
+impl Error {
+  /// An error from a custom filter or function
+  pub fn custom<S>(message: S) -> Self
+  where
+    S: ToString,
+  {
+    Self::Custom(message.to_string())
+  }
+}
+
+/// Gets one of the built-in filters by name
+pub fn builtin(name: &str) -> Option<&'static dyn Filter> {
+  let filter: &'static dyn Filter = match name {
+    "upper" => &upper,
+    "lower" => &lower,
+    "capitalize" => &capitalize,
+    "title" => &title,
+    "trim" => &trim,
+    "length" => &length,
+    "default" => &default,
+    "join" => &join,
+    "replace" => &replace,
+    "truncate" => &truncate,
+    "first" => &first,
+    "last" => &last,
+    "reverse" => &reverse,
+    "sort" => &sort,
+    _ => return None,
+  };
+
+  Some(filter)
+}
+
+fn arguments(args: &[Value], min: usize, max: usize, expected: &'static str) -> Result<(), Error> {
+  match (min..=max).contains(&args.len()) {
+    true => Ok(()),
+    false => Err(Error::ArgumentCount(expected, args.len())),
+  }
+}
+
+fn string(value: &Value) -> Result<&str, Error> {
+  match value {
+    Value::String(string) => Ok(string),
+    value => Err(Error::WrongType("a string", value.type_name())),
+  }
+}
+
+fn list(value: &Value) -> Result<&[Value], Error> {
+  match value {
+    Value::List(list) => Ok(list),
+    value => Err(Error::WrongType("a list", value.type_name())),
+  }
+}
+
+fn unsigned(value: &Value) -> Result<usize, Error> {
+  match value {
+    Value::Int(int) => usize::try_from(*int).ok(),
+    Value::UInt(uint) => usize::try_from(*uint).ok(),
+    _ => None,
+  }
+  .ok_or(Error::WrongType("a positive integer", value.type_name()))
+}
+
+fn map_string<F>(value: &Value, args: &[Value], f: F) -> Result<Value, Error>
+where
+  F: FnOnce(&str) -> String,
+{
+  arguments(args, 0, 0, "0")?;
+  Ok(Value::String(f(string(value)?)))
+}
+
+/// `hello` -> `Hello`, the rest is lowercased
+fn capitalize_word(word: &str) -> String {
+  let mut chars = word.chars();
+
+  match chars.next() {
+    Some(first) => first
+      .to_uppercase()
+      .chain(chars.flat_map(char::to_lowercase))
+      .collect(),
+    None => String::new(),
+  }
+}
+
+fn upper(value: &Value, args: &[Value]) -> Result<Value, Error> {
+  map_string(value, args, str::to_uppercase)
+}
+
+fn lower(value: &Value, args: &[Value]) -> Result<Value, Error> {
+  map_string(value, args, str::to_lowercase)
+}
+
+fn capitalize(value: &Value, args: &[Value]) -> Result<Value, Error> {
+  map_string(value, args, capitalize_word)
+}
+
+/// capitalizes every word, keeping the whitespace between them
+fn title(value: &Value, args: &[Value]) -> Result<Value, Error> {
+  map_string(value, args, |string| {
+    string
+      .split_inclusive(char::is_whitespace)
+      .map(capitalize_word)
+      .collect()
+  })
+}
+
+fn trim(value: &Value, args: &[Value]) -> Result<Value, Error> {
+  map_string(value, args, |string| string.trim().to_string())
+}
+
+/// characters of a string, or elements of a list or object
+fn length(value: &Value, args: &[Value]) -> Result<Value, Error> {
+  arguments(args, 0, 0, "0")?;
+
+  let length = match value {
+    Value::String(string) => string.chars().count(),
+    Value::List(list) => list.len(),
+    Value::Object(object) => object.len(),
+    value => {
+      return Err(Error::WrongType(
+        "a string, list or object",
+        value.type_name(),
+      ))
+    }
+  };
+
+  Ok(Value::UInt(length as u64))
+}
+
+/// `default(value)` replaces null, `default(value, true)` replaces anything falsy
+fn default(value: &Value, args: &[Value]) -> Result<Value, Error> {
+  arguments(args, 1, 2, "1 or 2")?;
+
+  let falsy = args.get(1).is_some_and(Value::is_truthy);
+  let replace = match falsy {
+    true => !value.is_truthy(),
+    false => *value == Value::Null,
+  };
+
+  Ok(if replace { &args[0] } else { value }.clone())
+}
+
+/// `join` or `join(", ")`
+fn join(value: &Value, args: &[Value]) -> Result<Value, Error> {
+  arguments(args, 0, 1, "0 or 1")?;
+
+  let separator = args.first().map(string).transpose()?.unwrap_or("");
+  Ok(Value::String(list(value)?.iter().join(separator)))
+}
+
+/// `replace("from", "to")`
+fn replace(value: &Value, args: &[Value]) -> Result<Value, Error> {
+  arguments(args, 2, 2, "2")?;
+
+  let (from, to) = (string(&args[0])?, string(&args[1])?);
+  Ok(Value::String(string(value)?.replace(from, to)))
+}
+
+/// `truncate(10)` keeps the first 10 characters followed by `...`,
+/// `truncate(10, "…")` uses `…` instead
+fn truncate(value: &Value, args: &[Value]) -> Result<Value, Error> {
+  arguments(args, 1, 2, "1 or 2")?;
+
+  let string = string(value)?;
+  let length = unsigned(&args[0])?;
+  let end = args.get(1).map(self::string).transpose()?.unwrap_or("...");
+
+  Ok(Value::String(match string.char_indices().nth(length) {
+    Some((index, _)) => format!("{}{end}", &string[..index]),
+    None => string.to_string(),
+  }))
+}
+
+/// first element of a list or character of a string, null if it's empty
+fn first(value: &Value, args: &[Value]) -> Result<Value, Error> {
+  arguments(args, 0, 0, "0")?;
+
+  Ok(match value {
+    Value::String(string) => string.chars().next().map(String::from).into(),
+    value => list(value)?.first().cloned().into(),
+  })
+}
+
+/// last element of a list or character of a string, null if it's empty
+fn last(value: &Value, args: &[Value]) -> Result<Value, Error> {
+  arguments(args, 0, 0, "0")?;
+
+  Ok(match value {
+    Value::String(string) => string.chars().next_back().map(String::from).into(),
+    value => list(value)?.last().cloned().into(),
+  })
+}
+
+fn reverse(value: &Value, args: &[Value]) -> Result<Value, Error> {
+  arguments(args, 0, 0, "0")?;
+
+  Ok(match value {
+    Value::String(string) => Value::String(string.chars().rev().collect()),
+    value => Value::List(list(value)?.iter().rev().cloned().collect()),
+  })
+}
+
+/// sorts a list of strings or numbers
+fn sort(value: &Value, args: &[Value]) -> Result<Value, Error> {
+  arguments(args, 0, 0, "0")?;
+
+  let mut list = list(value)?.to_vec();
+
+  for (a, b) in list.iter().tuple_windows() {
+    if compare_values(a, b).is_none() {
+      return Err(Error::Unsortable(a.type_name(), b.type_name()));
+    }
+  }
+
+  list.sort_by(|a, b| compare_values(a, b).unwrap_or(std::cmp::Ordering::Equal));
+
+  Ok(Value::List(list))
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::context::Value;
+  use crate::filter::{builtin, Error};
+
+  fn apply(name: &str, value: impl Into<Value>, args: &[Value]) -> Result<Value, Error> {
+    builtin(name).unwrap().apply(&value.into(), args)
+  }
+
+  fn list(values: &[&str]) -> Value {
+    Value::List(values.iter().map(|value| Value::from(*value)).collect())
+  }
+
+  #[test]
+  fn strings() {
+    assert_eq!(Ok("HELLO".into()), apply("upper", "hello", &[]));
+    assert_eq!(Ok("hello".into()), apply("lower", "HeLLo", &[]));
+    assert_eq!(
+      Ok("Hello world".into()),
+      apply("capitalize", "hELLO WORLD", &[])
+    );
+    assert_eq!(
+      Ok("Hello  World\n".into()),
+      apply("title", "hello  wORLD\n", &[])
+    );
+    assert_eq!(Ok("a b".into()), apply("trim", " a b \n", &[]));
+    assert_eq!(
+      Ok("a-b-c".into()),
+      apply("replace", "a b c", &[" ".into(), "-".into()])
+    );
+  }
+
+  #[test]
+  fn truncate() {
+    assert_eq!(
+      Ok("héll...".into()),
+      apply("truncate", "héllo", &[4.into()])
+    );
+    assert_eq!(Ok("héllo".into()), apply("truncate", "héllo", &[5.into()]));
+    assert_eq!(
+      Ok("h…".into()),
+      apply("truncate", "héllo", &[1.into(), "…".into()])
+    );
+    assert_eq!(
+      Err(Error::WrongType("a positive integer", "int")),
+      apply("truncate", "héllo", &[(-1).into()])
+    );
+  }
+
+  #[test]
+  fn lists() {
+    let letters = list(&["b", "c", "a"]);
+
+    assert_eq!(Ok(Value::UInt(3)), apply("length", letters.clone(), &[]));
+    assert_eq!(Ok(Value::UInt(2)), apply("length", "hé", &[]));
+    assert_eq!(
+      Ok("b, c, a".into()),
+      apply("join", letters.clone(), &[", ".into()])
+    );
+    assert_eq!(Ok("b".into()), apply("first", letters.clone(), &[]));
+    assert_eq!(Ok("a".into()), apply("last", letters.clone(), &[]));
+    assert_eq!(Ok(Value::Null), apply("first", list(&[]), &[]));
+    assert_eq!(Ok("o".into()), apply("last", "hello", &[]));
+    assert_eq!(
+      Ok(list(&["a", "c", "b"])),
+      apply("reverse", letters.clone(), &[])
+    );
+    assert_eq!(Ok("olleh".into()), apply("reverse", "hello", &[]));
+    assert_eq!(Ok(list(&["a", "b", "c"])), apply("sort", letters, &[]));
+  }
+
+  #[test]
+  fn sort_numbers() {
+    let numbers = vec![Value::Float(2.5), Value::Int(-1), Value::UInt(2)];
+
+    assert_eq!(
+      Ok(Value::List(vec![
+        Value::Int(-1),
+        Value::UInt(2),
+        Value::Float(2.5)
+      ])),
+      apply("sort", numbers, &[])
+    );
+    assert_eq!(
+      Err(Error::Unsortable("string", "int")),
+      apply("sort", vec!["a".into(), Value::Int(1)], &[])
+    );
+  }
+
+  #[test]
+  fn default() {
+    assert_eq!(
+      Ok("none".into()),
+      apply("default", Value::Null, &["none".into()])
+    );
+    assert_eq!(Ok("".into()), apply("default", "", &["none".into()]));
+    assert_eq!(
+      Ok("none".into()),
+      apply("default", "", &["none".into(), true.into()])
+    );
+    assert_eq!(
+      Ok("a".into()),
+      apply("default", "a", &["none".into(), true.into()])
+    );
+  }
+
+  #[test]
+  fn errors() {
+    assert!(builtin("missing").is_none());
+    assert_eq!(
+      Err(Error::WrongType("a string", "int")),
+      apply("upper", 1, &[])
+    );
+    assert_eq!(
+      Err(Error::ArgumentCount("0", 1)),
+      apply("upper", "a", &["b".into()])
+    );
+    assert_eq!(
+      Err(Error::ArgumentCount("2", 1)),
+      apply("replace", "a", &["b".into()])
+    );
+    assert_eq!(
+      "expected a list but got string",
+      apply("join", "a", &[]).unwrap_err().to_string()
+    );
+  }
+}
@@ -1,10 +1,19 @@
 use crate::context::Context;
-use crate::renderer::{Error, Renderer};
+use crate::renderer::Error;
+use crate::template::Template;
 use std::io::Write;
 
 pub mod context;
+pub mod diagnostic;
+pub mod environment;
+pub mod expression;
+pub mod filter;
+pub mod loader;
+pub mod parser;
+pub mod path;
 pub mod renderer;
-pub mod tokenizer;
+pub mod span;
+pub mod template;
 
 pub fn render_to_writer<C, S, W>(context: C, source: S, writer: W) -> Result<(), Error>
 where
@@ -12,9 +21,7 @@ where
   S: AsRef<str>,
   W: Write,
 {
-  Renderer::new(context.into(), source.as_ref(), writer).render()?;
-
-  Ok(())
+  Template::parse(source)?.render(&context.into(), writer)
 }
 
 pub fn render_to_string<C, S>(context: C, source: S) -> Result<String, Error>
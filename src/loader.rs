@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use thiserror::Error;
+
+use crate::parser::ParseError;
+
+/// Finds the source of a template by its name, for `$include "name"`
+pub trait TemplateLoader: Send + Sync {
+  fn load(&self, name: &str) -> io::Result<String>;
+}
+
+/// Templates kept in memory, by name
+impl TemplateLoader for HashMap<String, String> {
+  fn load(&self, name: &str) -> io::Result<String> {
+    self
+      .get(name)
+      .cloned()
+      .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "template not found"))
+  }
+}
+
+/// Templates in a directory, named by their path relative to it
+#[derive(Debug, Clone)]
+pub struct FileSystemLoader {
+  root: PathBuf,
+}
+
+impl FileSystemLoader {
+  pub fn new<P>(root: P) -> Self
+  where
+    P: Into<PathBuf>,
+  {
+    Self { root: root.into() }
+  }
+}
+
+impl TemplateLoader for FileSystemLoader {
+  /// Names can't point outside of the directory, like `/etc/passwd` or `../secret.txt`
+  fn load(&self, name: &str) -> io::Result<String> {
+    let path = Path::new(name);
+    let inside = path
+      .components()
+      .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+
+    if !inside {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "template names have to be relative paths inside the directory",
+      ));
+    }
+
+    std::fs::read_to_string(self.root.join(path))
+  }
+}
+
+/// A template that couldn't be loaded
+#[derive(Error, Debug)]
+#[error("{}")]
+pub enum LoadError {
+  #[error("can't load template {0}, the environment has no template loader")]
+  NoLoader(String),
+  #[error("can't load template {0}: {1}")]
+  Io(String, io::Error),
+  #[error("{0}")]
+  Parse(ParseError),
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+  use std::io::ErrorKind;
+
+  use crate::loader::{FileSystemLoader, TemplateLoader};
+
+  #[test]
+  fn memory() {
+    let loader = HashMap::from([("a".to_string(), "A".to_string())]);
+
+    assert_eq!("A", loader.load("a").unwrap());
+    assert_eq!(ErrorKind::NotFound, loader.load("b").unwrap_err().kind());
+  }
+
+  #[test]
+  fn file_system() {
+    let root = std::env::temp_dir().join(format!("simple_text_template_{}", std::process::id()));
+    std::fs::create_dir_all(root.join("partials")).unwrap();
+    std::fs::write(root.join("partials/header.txt"), "# $title\n").unwrap();
+
+    let loader = FileSystemLoader::new(&root);
+
+    assert_eq!("# $title\n", loader.load("partials/header.txt").unwrap());
+    assert_eq!("# $title\n", loader.load("./partials/header.txt").unwrap());
+    assert_eq!(
+      ErrorKind::NotFound,
+      loader.load("footer.txt").unwrap_err().kind()
+    );
+    assert_eq!(
+      ErrorKind::InvalidInput,
+      loader.load("../header.txt").unwrap_err().kind()
+    );
+    assert_eq!(
+      ErrorKind::InvalidInput,
+      loader.load("/etc/hostname").unwrap_err().kind()
+    );
+
+    std::fs::remove_dir_all(root).unwrap();
+  }
+}
@@ -0,0 +1,2494 @@
+use std::collections::HashSet;
+
+use thiserror::Error;
+
+use crate::context::Value;
+use crate::expression::{scan, split_string, Expr, ExpressionError, FilterCall};
+use crate::path;
+use crate::span::{Lines, Snippet, Span};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+  /// Any other text
+  Text(String),
+  /// `$name`, `$name|filter` or `$name|filter(args)|filter`
+  Var {
+    name: String,
+    filters: Vec<FilterCall>,
+    span: Span,
+  },
+  /// `$function(args)`, followed by any filters like a variable
+  Call {
+    name: String,
+    args: Vec<Expr>,
+    filters: Vec<FilterCall>,
+    span: Span,
+  },
+  /// `$// comment`, `$// comment //$` or a multi-line `$//` ... `//$`
+  Comment(String),
+  /// `$if condition: block $elif condition: block $else: block`
+  If {
+    /// `$if`, followed by any `$elif` / `$else if` arms
+    arms: Vec<IfArm>,
+    /// `$else:`
+    else_block: Option<Vec<Node>>,
+  },
+  /// `$for name in variable: block $else: block` or `$for key, name in variable: block`
+  For {
+    /// index of a list or key of an object
+    key: Option<String>,
+    name: String,
+    iterable: Iterable,
+    /// `$for name in variable if condition:`, skips the elements it's false for
+    condition: Option<Expr>,
+    block: Vec<Node>,
+    /// `$else:` or `$empty:`, when there's nothing to loop over
+    else_block: Option<Vec<Node>>,
+    span: Span,
+  },
+  /// `$include "name"`, renders a template from the environment's loader with the same context
+  Include { name: String, span: Span },
+  /// `$extends "name"`, renders the template it extends instead,
+  /// with the `$block`s of this template replacing its blocks
+  Extends { name: String, span: Span },
+  /// `$block name: block`, can be replaced by a template that extends this one
+  Block {
+    name: String,
+    block: Vec<Node>,
+    span: Span,
+  },
+  /// `$macro name(param, param = default): block`, called like a function with `$name(args)`
+  Macro {
+    name: String,
+    params: Vec<Param>,
+    block: Vec<Node>,
+    span: Span,
+  },
+  /// `$set name = value`, binds a variable in the scope it's in
+  Set {
+    name: String,
+    value: Expr,
+    span: Span,
+  },
+  /// `$with name = value, name = value: block`, binds variables only inside the block
+  With {
+    bindings: Vec<(String, Expr)>,
+    block: Vec<Node>,
+    span: Span,
+  },
+  /// `$break`, stops the `$for` it's in
+  Break,
+  /// `$continue`, skips to the next element of the `$for` it's in
+  Continue,
+}
+
+impl Node {
+  /// Every block of nodes inside this one that's rendered where it is,
+  /// which leaves out `$macro`s since they're rendered where they're called
+  pub fn blocks(&self) -> Vec<&[Node]> {
+    match self {
+      Node::If { arms, else_block } => arms
+        .iter()
+        .map(|arm| arm.block.as_slice())
+        .chain(else_block.as_deref())
+        .collect(),
+      Node::For {
+        block, else_block, ..
+      } => [Some(block.as_slice()), else_block.as_deref()]
+        .into_iter()
+        .flatten()
+        .collect(),
+      Node::Block { block, .. } | Node::With { block, .. } => vec![block],
+      _ => Vec::new(),
+    }
+  }
+}
+
+/// What a `$for` loops over
+#[derive(Debug, Clone, PartialEq)]
+pub enum Iterable {
+  /// a list or object like `items` or `object.items`
+  Variable(String),
+  /// `start..end`, `start..=end` or `range([start,] end[, step])`,
+  /// the end is only included with `..=`
+  Range {
+    start: Box<Expr>,
+    end: Box<Expr>,
+    step: Option<Box<Expr>>,
+    inclusive: bool,
+  },
+}
+
+/// A parameter of a `$macro`, `name` or `name = default`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Param {
+  pub name: String,
+  pub default: Option<Expr>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IfArm {
+  pub condition: Expr,
+  pub block: Vec<Node>,
+  pub span: Span,
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+#[error("{kind}\n{snippet}")]
+pub struct ParseError {
+  pub kind: ParseErrorKind,
+  pub snippet: Snippet,
+}
+
+impl ParseError {
+  pub fn span(&self) -> Span {
+    self.snippet.span
+  }
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+#[error("{}")]
+pub enum ParseErrorKind {
+  #[error("{0} block is missing its $end")]
+  UnclosedBlock(&'static str),
+  #[error("multi-line comment is missing its //$")]
+  UnclosedComment,
+  #[error("{0} is missing its `:`")]
+  MissingColon(&'static str),
+  #[error("{0} is missing its condition")]
+  MissingCondition(&'static str),
+  #[error("invalid condition, {0}")]
+  InvalidCondition(ExpressionError),
+  #[error("invalid arguments, {0}")]
+  InvalidArguments(ExpressionError),
+  #[error("invalid range, {0}")]
+  InvalidRange(ExpressionError),
+  #[error("invalid value, {0}")]
+  InvalidValue(ExpressionError),
+  #[error("range takes 1 to 3 arguments but got {0}")]
+  RangeArguments(usize),
+  #[error("malformed $for, expected `$for [key,] name in variable:` but got `$for {0}:`")]
+  MalformedFor(String),
+  #[error("unexpected {0}")]
+  Unexpected(&'static str),
+  #[error("malformed {0}, expected `{0} \"name\"` but got `{0} {1}`")]
+  MalformedTemplateName(&'static str, String),
+  #[error("malformed {0}, expected `{0} name = value` but got `{0} {1}`")]
+  MalformedAssignment(&'static str, String),
+  #[error("malformed $block, expected `$block name:` but got `$block {0}:`")]
+  MalformedBlock(String),
+  #[error(
+    "malformed $macro, expected `$macro name(param, param = default):` but got `$macro {0}:`"
+  )]
+  MalformedMacro(String),
+  #[error("{0} {1} is defined more than once")]
+  Duplicate(&'static str, String),
+  #[error("{0} can only be used inside a $for")]
+  OutsideLoop(&'static str),
+  #[error("blocks nested more than {0} levels deep")]
+  TooDeep(usize),
+}
+
+/// How many blocks can be nested in each other, so a huge template is an error
+/// instead of overflowing the stack when it's parsed or rendered
+const MAX_DEPTH: usize = 64;
+
+pub fn parse(source: &str) -> Result<Vec<Node>, ParseError> {
+  Parser::new(source).parse()
+}
+
+pub struct Parser<'a> {
+  source: &'a str,
+  lines: Lines<'a>,
+  offset: usize,
+  /// offset of the `$` of the last statement
+  start: usize,
+  /// how many blocks the parser is in, up to `MAX_DEPTH`
+  depth: usize,
+  /// set once blocks are nested too deep, everything after that gets skipped,
+  /// so the blocks it's in aren't unclosed
+  too_deep: bool,
+  /// how many `$for` blocks the parser is in
+  loops: usize,
+  /// whether there's been an `$extends`, there can only be one
+  extends: bool,
+  /// names of every `$block` so far
+  blocks: HashSet<String>,
+  /// names of every `$macro` so far
+  macros: HashSet<String>,
+  /// set when a statement removed its line, so the indent in front of it gets removed as well
+  removed_line: bool,
+  errors: Vec<ParseError>,
+}
+
+/// What ended a list of nodes
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum BlockEnd {
+  /// end of line, only for one-liners
+  Line,
+  /// end of source
+  Source,
+  /// `$end`
+  End,
+  /// `$else:`
+  Else,
+  /// `$empty:`
+  Empty,
+  /// `$elif` or `$else if`
+  Elif,
+}
+
+impl BlockEnd {
+  fn name(self) -> &'static str {
+    match self {
+      BlockEnd::Line => "end of line",
+      BlockEnd::Source => "end of source",
+      BlockEnd::End => "$end",
+      BlockEnd::Else => "$else",
+      BlockEnd::Empty => "$empty",
+      BlockEnd::Elif => "$elif",
+    }
+  }
+}
+
+// only lives until it's matched on, so boxing the node isn't worth it
+#[allow(clippy::large_enum_variant)]
+enum Statement {
+  Node(Node),
+  /// a statement with errors that can't be rendered
+  Invalid,
+  End(BlockEnd),
+}
+
+impl<'a> Parser<'a> {
+  pub fn new(source: &'a str) -> Self {
+    Self {
+      source,
+      lines: Lines::new(source),
+      offset: 0,
+      start: 0,
+      depth: 0,
+      too_deep: false,
+      loops: 0,
+      extends: false,
+      blocks: HashSet::new(),
+      macros: HashSet::new(),
+      removed_line: false,
+      errors: Vec::new(),
+    }
+  }
+
+  /// Parses the whole source, stopping at the first error
+  pub fn parse(self) -> Result<Vec<Node>, ParseError> {
+    match self.parse_recover() {
+      (nodes, errors) if errors.is_empty() => Ok(nodes),
+      (_, mut errors) => Err(errors.swap_remove(0)),
+    }
+  }
+
+  /// Parses the whole source, recovering from any errors it runs into,
+  /// returns everything it could parse along with every error
+  pub fn parse_recover(mut self) -> (Vec<Node>, Vec<ParseError>) {
+    let mut nodes = Vec::new();
+
+    loop {
+      let (block, end) = self.parse_nodes(false);
+      nodes.extend(block);
+
+      match end {
+        BlockEnd::Source => return (nodes, self.errors),
+        end => self.report(ParseErrorKind::Unexpected(end.name()), self.start),
+      }
+    }
+  }
+
+  /// Whether only spaces or tabs come before `start` on its line
+  fn at_line_start(&self, start: usize) -> bool {
+    let before = &self.source[..start];
+    let line = &before[before.rfind('\n').map_or(0, |index| index + 1)..];
+
+    line.chars().all(|c| c == ' ' || c == '\t')
+  }
+
+  /// Removes the newline at the start of `rest`, what's left of the line after a statement,
+  /// if the statement takes up its entire line, its indent gets removed as well
+  fn remove_line<'b>(&mut self, start: usize, rest: &'b str) -> &'b str {
+    let after = match rest.strip_prefix('\n') {
+      Some(after) => after,
+      None if rest.is_empty() => rest,
+      None => return rest,
+    };
+
+    match self.at_line_start(start) {
+      true => {
+        self.removed_line = true;
+        after
+      }
+      false => rest,
+    }
+  }
+
+  /// Whether the header of a block statement takes up its entire line,
+  /// so the block isn't a one-liner and the indent in front of it can be removed
+  fn header_fills_line(&self, start: usize) -> bool {
+    let rest = self.rest();
+    let line = &rest[..rest.find('\n').unwrap_or(rest.len())];
+
+    self.at_line_start(start)
+      && find_colon(line).is_some_and(|colon| line[colon + 1..].trim().is_empty())
+  }
+
+  fn rest(&self) -> &'a str {
+    &self.source[self.offset..]
+  }
+
+  fn skip_to(&mut self, rest: &str) {
+    self.offset = self.source.len() - rest.len();
+  }
+
+  /// Offset of a slice of the source
+  fn offset_of(&self, slice: &str) -> usize {
+    slice.as_ptr() as usize - self.source.as_ptr() as usize
+  }
+
+  fn span(&self, offset: usize) -> Span {
+    self.lines.span(offset)
+  }
+
+  fn report(&mut self, kind: ParseErrorKind, offset: usize) {
+    self.errors.push(ParseError {
+      kind,
+      snippet: Snippet::new(self.source, self.span(offset)),
+    });
+  }
+
+  /// Parses nodes until the end of the block,
+  /// one-liners also end at the end of the line
+  fn parse_nodes(&mut self, one_liner: bool) -> (Vec<Node>, BlockEnd) {
+    let mut nodes = Vec::new();
+    let mut escaped = false;
+
+    loop {
+      let (text, rest, end) = split_text(self.rest(), escaped);
+      escaped = end == TextEnd::Escaped;
+
+      if one_liner {
+        if let Some(line_end) = text.find('\n') {
+          push_text(&mut nodes, &text[..line_end]);
+          self.offset += line_end;
+          return (nodes, BlockEnd::Line);
+        }
+      }
+
+      push_text(&mut nodes, text);
+      self.skip_to(rest);
+
+      match end {
+        TextEnd::Source => return (nodes, BlockEnd::Source),
+        TextEnd::Escaped | TextEnd::Backslash => {}
+        TextEnd::Statement => {
+          let statement = self.parse_statement();
+
+          if std::mem::take(&mut self.removed_line) {
+            strip_indent(&mut nodes);
+          }
+
+          match statement {
+            Statement::Node(node) => nodes.push(node),
+            Statement::Invalid => {}
+            Statement::End(end) => {
+              if one_liner {
+                strip_trailing_space(&mut nodes);
+              }
+
+              return (nodes, end);
+            }
+          }
+        }
+      }
+    }
+  }
+
+  /// Parses a statement, starting right after its `$`
+  fn parse_statement(&mut self) -> Statement {
+    let rest = self.rest();
+    let start = self.offset - 1;
+    self.start = start;
+
+    if let Some(comment) = rest.strip_prefix("//") {
+      let (comment, rest) = split_comment(comment).unwrap_or_else(|| {
+        self.report(ParseErrorKind::UnclosedComment, start);
+        (comment.trim(), "")
+      });
+      let rest = self.remove_line(start, rest);
+      self.skip_to(rest);
+
+      return Statement::Node(Node::Comment(comment.to_string()));
+    }
+
+    let keyword_end = rest
+      .find(|c: char| !c.is_alphanumeric() && c != '_')
+      .unwrap_or(rest.len());
+    let after_keyword = &rest[keyword_end..];
+
+    match &rest[..keyword_end] {
+      "end" => {
+        let rest = match self.remove_line(start, after_keyword) {
+          rest if self.removed_line => rest,
+          _ => after_keyword
+            .strip_prefix([' ', '\n'])
+            .unwrap_or(after_keyword),
+        };
+        self.skip_to(rest);
+
+        Statement::End(BlockEnd::End)
+      }
+      "else" if after_keyword.trim_start_matches(' ').starts_with(':') => {
+        self.skip_to(after_keyword);
+        self.removed_line = self.header_fills_line(start);
+        Statement::End(BlockEnd::Else)
+      }
+      "empty" if after_keyword.trim_start_matches(' ').starts_with(':') => {
+        self.skip_to(after_keyword);
+        self.removed_line = self.header_fills_line(start);
+        Statement::End(BlockEnd::Empty)
+      }
+      "else" if after_keyword.trim_start_matches(' ').starts_with("if ") => {
+        self.skip_to(&after_keyword.trim_start_matches(' ')[2..]);
+        self.removed_line = self.header_fills_line(start);
+        Statement::End(BlockEnd::Elif)
+      }
+      "elif" if after_keyword.starts_with(' ') => {
+        self.skip_to(after_keyword);
+        self.removed_line = self.header_fills_line(start);
+        Statement::End(BlockEnd::Elif)
+      }
+      "break" => {
+        self.skip_to(after_keyword);
+        self.parse_loop_control(Node::Break, "$break", start)
+      }
+      "continue" => {
+        self.skip_to(after_keyword);
+        self.parse_loop_control(Node::Continue, "$continue", start)
+      }
+      "include" if after_keyword.starts_with(' ') => {
+        self.skip_to(after_keyword);
+        self
+          .parse_template_name("$include", start)
+          .map_or(Statement::Invalid, |name| {
+            Statement::Node(Node::Include {
+              name,
+              span: self.span(start),
+            })
+          })
+      }
+      "extends" if after_keyword.starts_with(' ') => {
+        self.skip_to(after_keyword);
+        self
+          .parse_extends(start)
+          .map_or(Statement::Invalid, Statement::Node)
+      }
+      "set" if after_keyword.starts_with(' ') => {
+        self.skip_to(after_keyword);
+        self
+          .parse_set(start)
+          .map_or(Statement::Invalid, Statement::Node)
+      }
+      "with" if after_keyword.starts_with(' ') => {
+        self.skip_to(after_keyword);
+        self
+          .parse_with(start)
+          .map_or(Statement::Invalid, Statement::Node)
+      }
+      "macro" if after_keyword.starts_with(' ') => {
+        self.skip_to(after_keyword);
+        self
+          .parse_macro(start)
+          .map_or(Statement::Invalid, Statement::Node)
+      }
+      "block" if after_keyword.starts_with(' ') => {
+        self.skip_to(after_keyword);
+        self
+          .parse_named_block(start)
+          .map_or(Statement::Invalid, Statement::Node)
+      }
+      "if" if after_keyword.starts_with(' ') => {
+        self.skip_to(after_keyword);
+        self
+          .parse_if(start)
+          .map_or(Statement::Invalid, Statement::Node)
+      }
+      "for" if after_keyword.starts_with(' ') => {
+        self.skip_to(after_keyword);
+        self
+          .parse_for(start)
+          .map_or(Statement::Invalid, Statement::Node)
+      }
+      _ => {
+        let name = &rest[..path::path_len(rest)];
+        self.offset += name.len();
+
+        if let Some(args) = self.parse_args() {
+          return Statement::Node(Node::Call {
+            name: name.to_string(),
+            args,
+            filters: self.parse_filters(),
+            span: self.span(start),
+          });
+        }
+
+        Statement::Node(Node::Var {
+          name: name.to_string(),
+          filters: self.parse_filters(),
+          span: self.span(start),
+        })
+      }
+    }
+  }
+
+  /// Parses the quoted template name of an `$include` or `$extends`,
+  /// if it takes up its entire line it gets removed along with its newline, like a comment
+  fn parse_template_name(&mut self, statement: &'static str, start: usize) -> Option<String> {
+    let rest = self.rest().trim_start_matches(' ');
+    let line = &rest[..rest.find('\n').unwrap_or(rest.len())];
+
+    let Some((name, len)) = line
+      .starts_with(['"', '\''])
+      .then(|| split_string(line))
+      .flatten()
+    else {
+      let kind = ParseErrorKind::MalformedTemplateName(statement, line.trim().to_string());
+      self.report(kind, start);
+      self.skip_to(&rest[line.len()..]);
+      return None;
+    };
+
+    let after = self.remove_line(start, &rest[len..]);
+    self.skip_to(after);
+
+    Some(name)
+  }
+
+  /// `$extends` can only be used once, outside of any blocks
+  fn parse_extends(&mut self, start: usize) -> Option<Node> {
+    let name = self.parse_template_name("$extends", start)?;
+
+    if self.depth > 0 || self.extends {
+      self.report(ParseErrorKind::Unexpected("$extends"), start);
+      return None;
+    }
+
+    self.extends = true;
+
+    Some(Node::Extends {
+      name,
+      span: self.span(start),
+    })
+  }
+
+  fn parse_named_block(&mut self, start: usize) -> Option<Node> {
+    let name = self.parse_header("$block", start);
+    let mut valid = is_name(name);
+
+    if !valid {
+      self.report(ParseErrorKind::MalformedBlock(name.to_string()), start);
+    } else if !self.blocks.insert(name.to_string()) {
+      self.report(ParseErrorKind::Duplicate("$block", name.to_string()), start);
+      valid = false;
+    }
+
+    let (block, end) = self.parse_block("$block", start);
+    self.skip_branches(end, "$block", start);
+
+    valid.then(|| Node::Block {
+      name: name.to_string(),
+      block,
+      span: self.span(start),
+    })
+  }
+
+  /// `$set` takes up the rest of its line,
+  /// if it takes up its entire line it gets removed along with its newline, like a comment
+  fn parse_set(&mut self, start: usize) -> Option<Node> {
+    let rest = self.rest();
+    let line = &rest[..rest.find('\n').unwrap_or(rest.len())];
+    let after = self.remove_line(start, &rest[line.len()..]);
+    self.skip_to(after);
+
+    let (name, value) = self.parse_assignment("$set", line, start)?;
+
+    Some(Node::Set {
+      name,
+      value,
+      span: self.span(start),
+    })
+  }
+
+  fn parse_with(&mut self, start: usize) -> Option<Node> {
+    let header = self.parse_header("$with", start);
+    let mut bindings = Vec::new();
+    let mut valid = true;
+
+    match split_params(header).filter(|split| !split.is_empty()) {
+      Some(split) => {
+        for binding in split {
+          match self.parse_assignment("$with", binding, start) {
+            Some(binding) => bindings.push(binding),
+            None => valid = false,
+          }
+        }
+      }
+      None => {
+        let kind = ParseErrorKind::MalformedAssignment("$with", header.to_string());
+        self.report(kind, start);
+        valid = false;
+      }
+    }
+
+    let (block, end) = self.parse_block("$with", start);
+    self.skip_branches(end, "$with", start);
+
+    valid.then(|| Node::With {
+      bindings,
+      block,
+      span: self.span(start),
+    })
+  }
+
+  /// Parses the `name = value` of a `$set` or `$with`
+  fn parse_assignment(
+    &mut self,
+    statement: &'static str,
+    assignment: &'a str,
+    start: usize,
+  ) -> Option<(String, Expr)> {
+    let Some((name, value)) = assignment
+      .split_once('=')
+      .filter(|(name, _)| is_name(name.trim()))
+    else {
+      let kind = ParseErrorKind::MalformedAssignment(statement, assignment.trim().to_string());
+      self.report(kind, start);
+      return None;
+    };
+
+    match Expr::parse(value) {
+      Ok(value) => Some((name.trim().to_string(), value)),
+      Err((error, offset)) => {
+        let offset = self.offset_of(value) + offset;
+        self.report(ParseErrorKind::InvalidValue(error), offset);
+        None
+      }
+    }
+  }
+
+  /// `$macro` can only be used outside of any blocks, like `$extends`, it's never rendered itself
+  fn parse_macro(&mut self, start: usize) -> Option<Node> {
+    let header = self.parse_header("$macro", start);
+    let signature = self.parse_signature(header, start);
+    let mut valid = signature.is_some();
+
+    if self.depth > 0 {
+      self.report(ParseErrorKind::Unexpected("$macro"), start);
+      valid = false;
+    } else if let Some((name, _)) = &signature {
+      if !self.macros.insert(name.clone()) {
+        self.report(ParseErrorKind::Duplicate("$macro", name.clone()), start);
+        valid = false;
+      }
+    }
+
+    let (block, end) = self.parse_block("$macro", start);
+    self.skip_branches(end, "$macro", start);
+
+    // a one-liner that takes up its entire line gets removed along with its newline, like a comment
+    if end == BlockEnd::Line {
+      let after = self.remove_line(start, self.rest());
+      self.skip_to(after);
+    }
+
+    let (name, params) = signature.filter(|_| valid)?;
+
+    Some(Node::Macro {
+      name,
+      params,
+      block,
+      span: self.span(start),
+    })
+  }
+
+  /// Parses the `name(param, param = default)` of a `$macro`,
+  /// parameters with a default have to come after the ones without
+  fn parse_signature(&mut self, header: &'a str, start: usize) -> Option<(String, Vec<Param>)> {
+    let name_end = header.find('(').unwrap_or(header.len());
+    let name = header[..name_end].trim_end();
+
+    let Some(params) = split_args(&header[name_end..])
+      .filter(|(_, rest)| is_name(name) && rest.trim().is_empty())
+      .and_then(|(params, _)| split_params(params))
+    else {
+      self.report(ParseErrorKind::MalformedMacro(header.to_string()), start);
+      return None;
+    };
+
+    let mut parsed: Vec<Param> = Vec::new();
+
+    for param in params {
+      let (param_name, default) = match param.split_once('=') {
+        Some((param_name, default)) => (param_name.trim(), Some(default)),
+        None => (param.trim(), None),
+      };
+      let after_default = parsed.last().is_some_and(|last| last.default.is_some());
+
+      if !is_name(param_name)
+        || parsed.iter().any(|other| other.name == param_name)
+        || (after_default && default.is_none())
+      {
+        self.report(ParseErrorKind::MalformedMacro(header.to_string()), start);
+        return None;
+      }
+
+      let default = match default.map(Expr::parse) {
+        Some(Ok(default)) => Some(default),
+        Some(Err((error, offset))) => {
+          let offset = self.offset_of(default.unwrap_or_default()) + offset;
+          self.report(ParseErrorKind::InvalidArguments(error), offset);
+          return None;
+        }
+        None => None,
+      };
+
+      parsed.push(Param {
+        name: param_name.to_string(),
+        default,
+      });
+    }
+
+    Some((name.to_string(), parsed))
+  }
+
+  fn parse_loop_control(&mut self, node: Node, statement: &'static str, start: usize) -> Statement {
+    if self.loops == 0 {
+      self.report(ParseErrorKind::OutsideLoop(statement), start);
+      return Statement::Invalid;
+    }
+
+    Statement::Node(node)
+  }
+
+  /// Parses `(args)` right after a function or filter, if there are any
+  fn parse_args(&mut self) -> Option<Vec<Expr>> {
+    let (args, rest) = split_args(self.rest())?;
+    self.skip_to(rest);
+
+    Some(Expr::parse_args(args).unwrap_or_else(|(error, offset)| {
+      let offset = self.offset_of(args) + offset;
+      self.report(ParseErrorKind::InvalidArguments(error), offset);
+      Vec::new()
+    }))
+  }
+
+  /// Parses any `|filter` or `|filter(args)` right after a variable,
+  /// a `|` that isn't followed by a name is left as text
+  fn parse_filters(&mut self) -> Vec<FilterCall> {
+    let mut filters = Vec::new();
+
+    while let Some(rest) = self.rest().strip_prefix('|') {
+      let name_end = rest
+        .find(|c: char| !c.is_alphanumeric() && c != '_')
+        .unwrap_or(rest.len());
+      let name = &rest[..name_end];
+
+      if !is_name(name) {
+        break;
+      }
+
+      self.offset += 1 + name.len();
+
+      filters.push(FilterCall {
+        name: name.to_string(),
+        args: self.parse_args().unwrap_or_default(),
+      });
+    }
+
+    filters
+  }
+
+  /// Parses the header of a statement up to its `:`, ignoring any `:` in quotes,
+  /// if it's missing, the rest of the line is used as the header
+  fn parse_header(&mut self, statement: &'static str, start: usize) -> &'a str {
+    let rest = self.rest();
+    let line_end = rest.find('\n').unwrap_or(rest.len());
+
+    match find_colon(&rest[..line_end]) {
+      Some(colon) => {
+        self.offset += colon + 1;
+        rest[..colon].trim()
+      }
+      None => {
+        self.report(ParseErrorKind::MissingColon(statement), start);
+        self.offset += line_end;
+        rest[..line_end].trim()
+      }
+    }
+  }
+
+  /// Parses a block, starting right after the `:` of its statement
+  ///
+  /// if there is any text after the `:` the block is a one-liner and ends at the end of the line,
+  /// otherwise it ends at its matching `$end`
+  fn parse_block(&mut self, statement: &'static str, start: usize) -> (Vec<Node>, BlockEnd) {
+    let rest = self.rest();
+    let line_end = rest.find('\n').unwrap_or(rest.len());
+    let one_liner = !rest[..line_end].trim().is_empty();
+    // a header that takes up its entire line removes its indent once the whole statement is done,
+    // the statements in its blocks come first, only the first block comes right after the header
+    let header = self.start == start && !one_liner && self.at_line_start(start);
+    let removed_line = std::mem::take(&mut self.removed_line) || header;
+
+    if one_liner {
+      self.skip_to(rest.strip_prefix(' ').unwrap_or(rest));
+    } else {
+      self.skip_to(rest.get(line_end + 1..).unwrap_or(""));
+    }
+
+    if self.depth >= MAX_DEPTH {
+      self.report(ParseErrorKind::TooDeep(MAX_DEPTH), start);
+      self.too_deep = true;
+      self.skip_to("");
+      return (Vec::new(), BlockEnd::Source);
+    }
+
+    self.depth += 1;
+    let (nodes, end) = self.parse_nodes(one_liner);
+    self.depth -= 1;
+    self.removed_line = removed_line;
+
+    if end == BlockEnd::Source && !one_liner && !self.too_deep {
+      self.report(ParseErrorKind::UnclosedBlock(statement), start);
+    }
+
+    (nodes, end)
+  }
+
+  /// Reports and skips over any `$else:`, `$empty:` or `$elif` that can't follow the block
+  fn skip_branches(&mut self, mut end: BlockEnd, statement: &'static str, start: usize) {
+    while let BlockEnd::Else | BlockEnd::Empty | BlockEnd::Elif = end {
+      let branch_start = self.start;
+      self.report(ParseErrorKind::Unexpected(end.name()), branch_start);
+      self.parse_header(end.name(), branch_start);
+      end = self.parse_block(statement, start).1;
+    }
+  }
+
+  fn parse_if(&mut self, start: usize) -> Option<Node> {
+    let mut valid = true;
+    let mut arms = Vec::new();
+    let mut statement = "$if";
+    let mut arm_start = start;
+
+    loop {
+      let header = self.parse_header(statement, arm_start);
+      let condition = match Expr::parse(header) {
+        Ok(condition) => Some(condition),
+        Err(_) if header.trim_start_matches(['!', ' ']).is_empty() => {
+          self.report(ParseErrorKind::MissingCondition(statement), arm_start);
+          None
+        }
+        Err((error, offset)) => {
+          let offset = self.offset_of(header) + offset;
+          self.report(ParseErrorKind::InvalidCondition(error), offset);
+          None
+        }
+      };
+
+      let (block, end) = self.parse_block("$if", start);
+
+      match condition {
+        Some(condition) => arms.push(IfArm {
+          condition,
+          block,
+          span: self.span(arm_start),
+        }),
+        None => valid = false,
+      }
+
+      let else_block = match end {
+        BlockEnd::Elif => {
+          statement = "$elif";
+          arm_start = self.start;
+          continue;
+        }
+        BlockEnd::Else => {
+          let else_start = self.start;
+          self.parse_header("$else", else_start);
+
+          let (block, end) = self.parse_block("$if", start);
+          self.skip_branches(end, "$if", start);
+
+          Some(block)
+        }
+        end => {
+          self.skip_branches(end, "$if", start);
+          None
+        }
+      };
+
+      return valid.then_some(Node::If { arms, else_block });
+    }
+  }
+
+  fn parse_for(&mut self, start: usize) -> Option<Node> {
+    let statement = self.parse_header("$for", start);
+    let (name, variable) = split_unquoted(statement, " in ").unwrap_or((statement, ""));
+    let (key, name) = match name.split_once(',') {
+      Some((key, name)) => (Some(key.trim()), name.trim()),
+      None => (None, name.trim()),
+    };
+    // a header like `a in b if` is missing its condition
+    let split =
+      split_unquoted(variable, " if ").or_else(|| Some((variable.strip_suffix(" if")?, "")));
+    let (variable, condition) = match split {
+      Some((variable, condition)) => (variable.trim(), Some(condition)),
+      None => (variable.trim(), None),
+    };
+
+    let mut valid = is_name(name) && key.is_none_or(is_name) && !variable.is_empty();
+
+    if !valid {
+      self.report(ParseErrorKind::MalformedFor(statement.to_string()), start);
+    }
+
+    let iterable = match valid {
+      true => self.parse_iterable(variable),
+      false => None,
+    };
+
+    let condition = match condition.map(Expr::parse).transpose() {
+      Ok(condition) => condition,
+      Err(_) if condition.is_some_and(|condition| condition.trim().is_empty()) => {
+        self.report(ParseErrorKind::MissingCondition("$for"), start);
+        valid = false;
+        None
+      }
+      Err((error, offset)) => {
+        let offset = self.offset_of(condition.unwrap_or_default()) + offset;
+        self.report(ParseErrorKind::InvalidCondition(error), offset);
+        valid = false;
+        None
+      }
+    };
+
+    self.loops += 1;
+    let (block, end) = self.parse_block("$for", start);
+    self.loops -= 1;
+
+    let else_block = match end {
+      BlockEnd::Else | BlockEnd::Empty => {
+        let else_start = self.start;
+        self.parse_header(end.name(), else_start);
+
+        let (block, end) = self.parse_block("$for", start);
+        self.skip_branches(end, "$for", start);
+
+        Some(block)
+      }
+      end => {
+        self.skip_branches(end, "$for", start);
+        None
+      }
+    };
+
+    let iterable = iterable.filter(|_| valid)?;
+
+    Some(Node::For {
+      key: key.map(str::to_string),
+      name: name.to_string(),
+      iterable,
+      condition,
+      block,
+      else_block,
+      span: self.span(start),
+    })
+  }
+
+  /// Parses what a `$for` loops over, a range if it's `range(args)` or has a `..`,
+  /// otherwise a variable
+  fn parse_iterable(&mut self, source: &'a str) -> Option<Iterable> {
+    if let Some((args, "")) = source.strip_prefix("range").and_then(split_args) {
+      let args = Expr::parse_args(args).map_err(|(error, offset)| {
+        let offset = self.offset_of(args) + offset;
+        self.report(ParseErrorKind::InvalidRange(error), offset);
+      });
+      let mut args = args.ok()?.into_iter();
+
+      let (start, end) = match args.len() {
+        1 => (Expr::Literal(Value::Int(0)), args.next()?),
+        2 | 3 => (args.next()?, args.next()?),
+        count => {
+          self.report(
+            ParseErrorKind::RangeArguments(count),
+            self.offset_of(source),
+          );
+          return None;
+        }
+      };
+
+      return Some(Iterable::Range {
+        start: Box::new(start),
+        end: Box::new(end),
+        step: args.next().map(Box::new),
+        inclusive: false,
+      });
+    }
+
+    let Some((start, end)) = split_unquoted(source, "..") else {
+      return Some(Iterable::Variable(source.to_string()));
+    };
+    let (end, inclusive) = match end.strip_prefix('=') {
+      Some(end) => (end, true),
+      None => (end, false),
+    };
+
+    let mut parse = |source: &str| {
+      Expr::parse(source)
+        .map_err(|(error, offset)| {
+          let offset = self.offset_of(source) + offset;
+          self.report(ParseErrorKind::InvalidRange(error), offset);
+        })
+        .ok()
+    };
+    let (start, end) = (parse(start), parse(end));
+
+    Some(Iterable::Range {
+      start: Box::new(start?),
+      end: Box::new(end?),
+      step: None,
+      inclusive,
+    })
+  }
+}
+
+fn is_name(name: &str) -> bool {
+  name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+    && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+fn push_text(nodes: &mut Vec<Node>, text: &str) {
+  if text.is_empty() {
+    return;
+  }
+
+  match nodes.last_mut() {
+    Some(Node::Text(last)) => last.push_str(text),
+    _ => nodes.push(Node::Text(text.to_string())),
+  }
+}
+
+/// Removes the spaces and tabs a line starts with in front of a statement that removed its line
+fn strip_indent(nodes: &mut Vec<Node>) {
+  if let Some(Node::Text(text)) = nodes.last_mut() {
+    text.truncate(text.trim_end_matches([' ', '\t']).len());
+
+    if text.is_empty() {
+      nodes.pop();
+    }
+  }
+}
+
+/// one-liners ignore the space in front of `$end`, `$else` and `$elif`
+fn strip_trailing_space(nodes: &mut Vec<Node>) {
+  if let Some(Node::Text(text)) = nodes.last_mut() {
+    if text.ends_with(' ') {
+      text.pop();
+    }
+
+    if text.is_empty() {
+      nodes.pop();
+    }
+  }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum TextEnd {
+  /// a `$` that starts a statement
+  Statement,
+  /// `\$` or `$$`, the rest starts with the `$` that should be kept as text
+  Escaped,
+  /// `\\` before a `$`, the rest starts with that `$`
+  Backslash,
+  /// end of source
+  Source,
+}
+
+/// Splits text off of the start of the source, returns `(text, rest, how it ended)`
+///
+/// `\$` and `$$` are escaped `$`, a `$` that isn't followed by a name (like `$5.00`) is kept as is,
+/// `\\` right before a `$` is an escaped `\`, `escaped` means the source starts with an escaped `$`
+fn split_text(source: &str, escaped: bool) -> (&str, &str, TextEnd) {
+  let mut offset = usize::from(escaped);
+
+  loop {
+    let start = match source[offset..].find('$') {
+      None => return (source, "", TextEnd::Source),
+      Some(index) => offset + index,
+    };
+
+    let text = &source[..start];
+    let statement = &source[start + 1..];
+
+    let backslashes = text.len() - text.trim_end_matches('\\').len();
+
+    if backslashes > 0 {
+      // every pair is one `\`, one left over escapes the `$`
+      let text = &text[..text.len() - backslashes / 2 - backslashes % 2];
+      let end = match backslashes % 2 {
+        0 => TextEnd::Backslash,
+        _ => TextEnd::Escaped,
+      };
+
+      return (text, &source[start..], end);
+    }
+
+    if statement.starts_with('$') {
+      return (text, statement, TextEnd::Escaped);
+    }
+
+    if statement.starts_with(|c: char| c.is_alphabetic() || c == '_') || statement.starts_with("//")
+    {
+      return (text, statement, TextEnd::Statement);
+    }
+
+    offset = start + 1;
+  }
+}
+
+/// Splits a comment, starting right after its `$//`, into `(comment, rest)`,
+/// returns `None` if a multi-line comment is never closed
+///
+/// `$//` followed by the end of its line starts a multi-line comment that ends at `//$`,
+/// a `//$` later on the same line ends an inline comment, otherwise it's a line comment.
+fn split_comment(source: &str) -> Option<(&str, &str)> {
+  let line_end = source.find('\n').unwrap_or(source.len());
+  let line = &source[..line_end];
+
+  let end = match line.trim().is_empty() {
+    true => source.find("//$"),
+    false => line.find("//$"),
+  };
+
+  let (comment, rest) = match end {
+    Some(end) => (&source[..end], &source[end + 3..]),
+    None if line.trim().is_empty() => return None,
+    None => (line, &source[line_end..]),
+  };
+
+  Some((comment.trim(), rest))
+}
+
+/// Splits the arguments of a filter off of the start of the source, returns `(args, rest)`
+/// without the parentheses, or `None` if it doesn't start with `(` or it's never closed
+fn split_args(source: &str) -> Option<(&str, &str)> {
+  let args = source.strip_prefix('(')?;
+  let line = &args[..args.find('\n').unwrap_or(args.len())];
+  let end = scan(line, |rest, depth| depth == 0 && rest.starts_with(')'))?;
+
+  Some((&args[..end], &args[end + 1..]))
+}
+
+/// Splits the parameters of a `$macro` or the bindings of a `$with` on the commas between them,
+/// skipping over quoted strings and brackets, `None` if any of them are empty
+fn split_params(params: &str) -> Option<Vec<&str>> {
+  if params.trim().is_empty() {
+    return Some(Vec::new());
+  }
+
+  let mut split = Vec::new();
+  let mut rest = params;
+
+  while let Some(comma) = scan(rest, |rest, depth| depth == 0 && rest.starts_with(',')) {
+    split.push(&rest[..comma]);
+    rest = &rest[comma + 1..];
+  }
+
+  split.push(rest);
+
+  match split.iter().any(|param| param.trim().is_empty()) {
+    true => None,
+    false => Some(split),
+  }
+}
+
+/// Splits `source` around the first `pattern` that isn't quoted or inside brackets
+fn split_unquoted<'s>(source: &'s str, pattern: &str) -> Option<(&'s str, &'s str)> {
+  let index = scan(source, |rest, depth| {
+    depth == 0 && rest.starts_with(pattern)
+  })?;
+
+  Some((&source[..index], &source[index + pattern.len()..]))
+}
+
+/// Finds the `:` that ends a header, skipping over any quoted strings
+fn find_colon(header: &str) -> Option<usize> {
+  scan(header, |rest, _| rest.starts_with(':'))
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::expression::{Expr, ExpressionError};
+  use crate::parser::{IfArm, Iterable, Node, ParseErrorKind};
+  use crate::span::Span;
+
+  /// parses without any spans, so tests only have to care about the structure
+  fn parse(source: &str) -> Result<Vec<Node>, ParseErrorKind> {
+    let mut nodes = crate::parser::parse(source).map_err(|error| error.kind)?;
+    clear_spans(&mut nodes);
+    Ok(nodes)
+  }
+
+  fn clear_spans(nodes: &mut [Node]) {
+    for node in nodes {
+      match node {
+        Node::Var { span, .. }
+        | Node::Call { span, .. }
+        | Node::Include { span, .. }
+        | Node::Extends { span, .. }
+        | Node::Set { span, .. } => *span = Span::default(),
+        Node::Block { block, span, .. }
+        | Node::Macro { block, span, .. }
+        | Node::With { block, span, .. } => {
+          *span = Span::default();
+          clear_spans(block);
+        }
+        Node::If { arms, else_block } => {
+          for arm in arms {
+            arm.span = Span::default();
+            clear_spans(&mut arm.block);
+          }
+
+          if let Some(block) = else_block {
+            clear_spans(block);
+          }
+        }
+        Node::For {
+          block,
+          else_block,
+          span,
+          ..
+        } => {
+          *span = Span::default();
+          clear_spans(block);
+
+          if let Some(block) = else_block {
+            clear_spans(block);
+          }
+        }
+        Node::Text(_) | Node::Comment(_) | Node::Break | Node::Continue => {}
+      }
+    }
+  }
+
+  fn text(text: &str) -> Node {
+    Node::Text(text.to_string())
+  }
+
+  fn var(name: &str) -> Node {
+    Node::Var {
+      name: name.to_string(),
+      filters: Vec::new(),
+      span: Span::default(),
+    }
+  }
+
+  fn comment(comment: &str) -> Node {
+    Node::Comment(comment.to_string())
+  }
+
+  mod text_and_variables {
+    use super::*;
+
+    #[test]
+    fn empty_text() {
+      assert_eq!(Ok(vec![]), parse(""));
+    }
+
+    #[test]
+    fn normal_text() {
+      assert_eq!(Ok(vec![text("some text")]), parse("some text"));
+    }
+
+    #[test]
+    fn variable() {
+      assert_eq!(Ok(vec![var("variable")]), parse("$variable"));
+    }
+
+    #[test]
+    fn variable_with_inline() {
+      assert_eq!(
+        Ok(vec![text("123 "), var("variable"), text(" 456")]),
+        parse("123 $variable 456")
+      );
+    }
+
+    #[test]
+    fn variable_with_newline() {
+      assert_eq!(
+        Ok(vec![text("\n"), var("variable"), text("\n")]),
+        parse("\n$variable\n")
+      );
+    }
+
+    #[test]
+    fn variable_multi() {
+      assert_eq!(
+        Ok(vec![
+          var("variable1"),
+          text(" "),
+          var("variable2"),
+          var("variable3")
+        ]),
+        parse("$variable1 $variable2$variable3")
+      );
+    }
+
+    #[test]
+    fn variable_with_punctuation() {
+      assert_eq!(
+        Ok(vec![
+          text("["),
+          var("item.name"),
+          text("]("),
+          var("item.url"),
+          text(").")
+        ]),
+        parse("[$item.name]($item.url).")
+      );
+    }
+  }
+
+  mod filters {
+    use super::*;
+    use crate::expression::{ExpressionError, FilterCall};
+
+    fn filtered(name: &str, filters: &[(&str, &str)]) -> Node {
+      Node::Var {
+        name: name.to_string(),
+        filters: filters
+          .iter()
+          .map(|(name, args)| FilterCall {
+            name: name.to_string(),
+            args: Expr::parse_args(args).unwrap(),
+          })
+          .collect(),
+        span: Span::default(),
+      }
+    }
+
+    #[test]
+    fn pipeline() {
+      assert_eq!(
+        Ok(vec![
+          filtered("name", &[("upper", ""), ("trim", "")]),
+          text("!")
+        ]),
+        parse("$name|upper|trim!")
+      );
+    }
+
+    #[test]
+    fn arguments() {
+      assert_eq!(
+        Ok(vec![
+          filtered(
+            "a.b",
+            &[("replace", "\"(\", \")\""), ("truncate", "10, other")]
+          ),
+          text(" after")
+        ]),
+        parse("$a.b|replace(\"(\", \")\")|truncate(10, other) after")
+      );
+      assert_eq!(
+        Ok(vec![filtered("a", &[("join", "")]), text("\n")]),
+        parse("$a|join()\n")
+      );
+    }
+
+    #[test]
+    fn not_a_filter() {
+      assert_eq!(
+        Ok(vec![var("a"), text("| b |"), var("c"), text("|1")]),
+        parse("$a| b |$c|1")
+      );
+      assert_eq!(
+        Ok(vec![filtered("a", &[("upper", "")]), text("(unclosed")]),
+        parse("$a|upper(unclosed")
+      );
+    }
+
+    #[test]
+    fn function_call() {
+      assert_eq!(
+        Ok(vec![
+          Node::Call {
+            name: "link".to_string(),
+            args: Expr::parse_args("id, \"(\"").unwrap(),
+            filters: vec![FilterCall {
+              name: "upper".to_string(),
+              args: vec![],
+            }],
+            span: Span::default(),
+          },
+          text(" "),
+          Node::Call {
+            name: "now".to_string(),
+            args: vec![],
+            filters: vec![],
+            span: Span::default(),
+          },
+        ]),
+        parse("$link(id, \"(\")|upper $now()")
+      );
+    }
+
+    #[test]
+    fn invalid_arguments() {
+      assert_eq!(
+        Err(ParseErrorKind::InvalidArguments(
+          ExpressionError::Unexpected("b".to_string())
+        )),
+        parse("$a|replace(a b)")
+      );
+    }
+  }
+
+  mod escapes {
+    use super::*;
+
+    #[test]
+    fn backslash() {
+      assert_eq!(
+        Ok(vec![text("echo $HOME $"), var("variable")]),
+        parse("echo \\$HOME \\$$variable")
+      );
+    }
+
+    #[test]
+    fn escaped_backslash() {
+      assert_eq!(
+        Ok(vec![
+          text("C:\\Users\\"),
+          var("user"),
+          text("\\$a \\\\ \\$5")
+        ]),
+        parse("C:\\Users\\\\$user\\\\\\$a \\\\ \\\\$5")
+      );
+    }
+
+    #[test]
+    fn double_dollar() {
+      assert_eq!(
+        Ok(vec![text("$HOME $"), var("variable")]),
+        parse("$$HOME $$$variable")
+      );
+    }
+
+    #[test]
+    fn not_a_name() {
+      assert_eq!(
+        Ok(vec![text("costs $5.00 $ or more")]),
+        parse("costs $5.00 $ or more")
+      );
+    }
+
+    #[test]
+    fn escaped_in_block() {
+      assert_eq!(
+        Ok(vec![Node::If {
+          arms: vec![IfArm {
+            condition: Expr::Var("a".to_string()),
+            block: vec![text("$end $end\n")],
+            span: Span::default(),
+          }],
+          else_block: None,
+        }]),
+        parse("$if a:\n\\$end $$end\n$end\n")
+      );
+    }
+  }
+
+  mod comments {
+    use super::*;
+
+    #[test]
+    fn line_comment() {
+      assert_eq!(
+        Ok(vec![
+          comment("comment"),
+          text("text "),
+          comment("another comment"),
+          text("\n")
+        ]),
+        parse("$// comment\ntext $// another comment\n")
+      );
+    }
+
+    #[test]
+    fn multiline_comment() {
+      assert_eq!(
+        Ok(vec![comment("multi\nline\ncomment"), text("text")]),
+        parse("$//\nmulti\nline\ncomment\n//$\ntext")
+      );
+    }
+
+    #[test]
+    fn inline_comment() {
+      assert_eq!(
+        Ok(vec![text("a "), comment("in line comment"), text(" b")]),
+        parse("a $// in line comment //$ b")
+      );
+    }
+
+    #[test]
+    fn unclosed_comment() {
+      assert_eq!(
+        Err(ParseErrorKind::UnclosedComment),
+        parse("$//\ncomment\n")
+      );
+    }
+
+    #[test]
+    fn comment_in_block() {
+      assert_eq!(
+        Ok(vec![Node::If {
+          arms: vec![IfArm {
+            condition: Expr::Var("a".to_string()),
+            block: vec![comment("$end"), text("a\n")],
+            span: Span::default(),
+          }],
+          else_block: None,
+        }]),
+        parse("$if a:\n$// $end\na\n$end\n")
+      );
+    }
+  }
+
+  mod includes {
+    use super::*;
+
+    fn include(name: &str) -> Node {
+      Node::Include {
+        name: name.to_string(),
+        span: Span::default(),
+      }
+    }
+
+    #[test]
+    fn include_line() {
+      assert_eq!(
+        Ok(vec![
+          include("header.txt"),
+          text("# Title\n"),
+          include("footer.txt")
+        ]),
+        parse("$include \"header.txt\"\n# Title\n$include 'footer.txt'\n")
+      );
+      assert_eq!(
+        Ok(vec![text("a\n"), include("b"), text("c")]),
+        parse("a\n  $include \"b\"\nc")
+      );
+    }
+
+    #[test]
+    fn include_inline() {
+      assert_eq!(
+        Ok(vec![
+          text("a "),
+          include("b \"c\""),
+          text(" d\n"),
+          Node::If {
+            arms: vec![IfArm {
+              condition: Expr::Var("e".to_string()),
+              block: vec![include("f")],
+              span: Span::default(),
+            }],
+            else_block: None,
+          }
+        ]),
+        parse("a $include \"b \\\"c\\\"\" d\n$if e: $include \"f\"")
+      );
+    }
+
+    #[test]
+    fn include_invalid() {
+      assert_eq!(
+        Err(ParseErrorKind::MalformedTemplateName(
+          "$include",
+          "header.txt".to_string()
+        )),
+        parse("$include header.txt\n")
+      );
+      assert_eq!(
+        Err(ParseErrorKind::MalformedTemplateName(
+          "$include",
+          "\"header.txt".to_string()
+        )),
+        parse("$include \"header.txt\n\"")
+      );
+      assert_eq!(Ok(vec![var("include")]), parse("$include"));
+    }
+  }
+
+  mod blocks {
+    use super::*;
+
+    fn block(name: &str, block: Vec<Node>) -> Node {
+      Node::Block {
+        name: name.to_string(),
+        block,
+        span: Span::default(),
+      }
+    }
+
+    #[test]
+    fn extends() {
+      assert_eq!(
+        Ok(vec![
+          Node::Extends {
+            name: "base.txt".to_string(),
+            span: Span::default(),
+          },
+          block("title", vec![text("Report")]),
+          block(
+            "body",
+            vec![text("a\n"), block("inner", vec![var("b")]), text("\n")]
+          ),
+        ]),
+        parse("$extends \"base.txt\"\n$block title: Report $end\n$block body:\na\n$block inner: $b\n$end\n")
+      );
+    }
+
+    #[test]
+    fn blocks_invalid() {
+      assert_eq!(
+        Err(ParseErrorKind::MalformedBlock("a b".to_string())),
+        parse("$block a b: c")
+      );
+      assert_eq!(
+        Err(ParseErrorKind::Duplicate("$block", "a".to_string())),
+        parse("$block a: b\n$if c:\n$block a: d\n$end\n")
+      );
+      assert_eq!(
+        Err(ParseErrorKind::Unexpected("$extends")),
+        parse("$extends \"a\"\n$extends \"b\"\n")
+      );
+      assert_eq!(
+        Err(ParseErrorKind::Unexpected("$extends")),
+        parse("$block a:\n$extends \"b\"\n$end\n")
+      );
+      assert_eq!(
+        Err(ParseErrorKind::MalformedTemplateName(
+          "$extends",
+          "base".to_string()
+        )),
+        parse("$extends base")
+      );
+    }
+  }
+
+  mod macros {
+    use super::*;
+    use crate::context::Value;
+    use crate::parser::Param;
+
+    fn param(name: &str, default: Option<Value>) -> Param {
+      Param {
+        name: name.to_string(),
+        default: default.map(Expr::Literal),
+      }
+    }
+
+    #[test]
+    fn definition() {
+      assert_eq!(
+        Ok(vec![
+          Node::Macro {
+            name: "badge".to_string(),
+            params: vec![
+              param("label", None),
+              param("color", Some(Value::String("a, b".to_string()))),
+            ],
+            block: vec![var("label"), text(" "), var("color")],
+            span: Span::default(),
+          },
+          Node::Macro {
+            name: "hr".to_string(),
+            params: Vec::new(),
+            block: vec![text("---\n")],
+            span: Span::default(),
+          },
+          text("a"),
+        ]),
+        parse("$macro badge(label, color = \"a, b\"): $label $color\n$macro hr():\n---\n$end\na")
+      );
+    }
+
+    #[test]
+    fn definition_invalid() {
+      let malformed = |header: &str| Err(ParseErrorKind::MalformedMacro(header.to_string()));
+
+      assert_eq!(malformed("badge"), parse("$macro badge: a"));
+      assert_eq!(malformed("badge(a b)"), parse("$macro badge(a b): a"));
+      assert_eq!(malformed("badge(a,)"), parse("$macro badge(a,): a"));
+      assert_eq!(malformed("badge(a, a)"), parse("$macro badge(a, a): a"));
+      assert_eq!(
+        malformed("badge(a = 1, b)"),
+        parse("$macro badge(a = 1, b): a")
+      );
+      assert!(matches!(
+        parse("$macro badge(a = 1 +): a"),
+        Err(ParseErrorKind::InvalidArguments(_))
+      ));
+      assert_eq!(
+        Err(ParseErrorKind::Duplicate("$macro", "a".to_string())),
+        parse("$macro a(): a\n$macro a(): b\n")
+      );
+      assert_eq!(
+        Err(ParseErrorKind::Unexpected("$macro")),
+        parse("$if a:\n$macro b(): c\n$end\n")
+      );
+    }
+  }
+
+  mod assignments {
+    use super::*;
+    use crate::context::Value;
+
+    #[test]
+    fn set() {
+      assert_eq!(
+        Ok(vec![
+          text("a\n"),
+          Node::Set {
+            name: "title".to_string(),
+            value: Expr::Var("page.title".to_string()),
+            span: Span::default(),
+          },
+          text("b "),
+          Node::Set {
+            name: "c".to_string(),
+            value: Expr::Literal(Value::Int(1)),
+            span: Span::default(),
+          },
+          text("\n"),
+        ]),
+        parse("a\n$set title = page.title\nb $set c = 1\n")
+      );
+    }
+
+    #[test]
+    fn with() {
+      assert_eq!(
+        Ok(vec![Node::With {
+          bindings: vec![
+            ("a".to_string(), Expr::Var("b.c".to_string())),
+            (
+              "d".to_string(),
+              Expr::Literal(Value::String("e, f".to_string()))
+            ),
+          ],
+          block: vec![var("a"), text(" "), var("d")],
+          span: Span::default(),
+        }]),
+        parse("$with a = b.c, d = \"e, f\": $a $d")
+      );
+    }
+
+    #[test]
+    fn assignments_invalid() {
+      assert_eq!(
+        Err(ParseErrorKind::MalformedAssignment(
+          "$set",
+          "a b = c".to_string()
+        )),
+        parse("$set a b = c")
+      );
+      assert_eq!(
+        Err(ParseErrorKind::MalformedAssignment(
+          "$with",
+          "a".to_string()
+        )),
+        parse("$with a: b")
+      );
+      assert_eq!(
+        Err(ParseErrorKind::MalformedAssignment("$with", "".to_string())),
+        parse("$with : b")
+      );
+      assert!(matches!(
+        parse("$set a = b +"),
+        Err(ParseErrorKind::InvalidValue(_))
+      ));
+    }
+  }
+
+  #[test]
+  fn truncated_templates() {
+    let source =
+      "a $if b.c: $b \\$ $elif !d:\n$//\nx //$\n$for e in f:\n$e $// y\n$end\n$else:\n$$\n$end\n";
+
+    for (end, _) in source.char_indices() {
+      let _ = parse(&source[..end]);
+    }
+
+    assert!(parse(source).is_ok());
+  }
+
+  #[test]
+  fn too_deep() {
+    let nested = |depth| format!("{}x\n{}", "$if a:\n".repeat(depth), "$end\n".repeat(depth));
+    let errors = |source: &str| {
+      let (_, errors) = crate::parser::Parser::new(source).parse_recover();
+      errors
+        .into_iter()
+        .map(|error| (error.span().line, error.kind))
+        .collect::<Vec<_>>()
+    };
+
+    assert!(parse(&nested(64)).is_ok());
+    assert_eq!(vec![(65, ParseErrorKind::TooDeep(64))], errors(&nested(65)));
+    assert_eq!(
+      vec![(1, ParseErrorKind::TooDeep(64))],
+      errors(&format!("{}x", "$if a: ".repeat(1000)))
+    );
+    assert_eq!(
+      Err(ParseErrorKind::InvalidCondition(ExpressionError::TooDeep(
+        64
+      ))),
+      parse(&format!("$if {}a{}: x", "(".repeat(3000), ")".repeat(3000)))
+    );
+  }
+
+  mod spans {
+    use crate::parser::{parse, Node};
+    use crate::span::Span;
+
+    fn at(line: usize, column: usize, offset: usize) -> Span {
+      Span {
+        line,
+        column,
+        offset,
+      }
+    }
+
+    #[test]
+    fn node_spans() {
+      let nodes = parse("a\n $b\n$for c in d:\n  $if e: $c\n$end\n").unwrap();
+
+      assert!(matches!(&nodes[1], Node::Var { span, .. } if *span == at(2, 2, 3)));
+
+      let Node::For {
+        block,
+        span: for_span,
+        ..
+      } = &nodes[3]
+      else {
+        panic!("expected a $for, got {:?}", nodes[3]);
+      };
+      assert_eq!(at(3, 1, 6), *for_span);
+
+      let Node::If { arms, .. } = &block[1] else {
+        panic!("expected an $if, got {:?}", block[1]);
+      };
+      assert_eq!(at(4, 3, 21), arms[0].span);
+      assert!(matches!(&arms[0].block[0], Node::Var { span, .. } if *span == at(4, 10, 28)));
+    }
+
+    #[test]
+    fn error_spans() {
+      let error = parse("line 1\nline 2 $if a\n").unwrap_err();
+      assert_eq!(at(2, 8, 14), error.span());
+      assert_eq!(
+        "$if is missing its `:`\n --> 2:8\n  |\n2 | line 2 $if a\n  |        ^",
+        error.to_string()
+      );
+
+      let error = parse("$if a:\n  $for b in c:\n$b\n$end\n").unwrap_err();
+      assert_eq!(at(1, 1, 0), error.span());
+
+      let error = parse("$if a: a $elif !: b").unwrap_err();
+      assert_eq!(at(1, 10, 9), error.span());
+
+      let error = parse("a\n$if a: b $else: c $else: d").unwrap_err();
+      assert_eq!(at(2, 19, 20), error.span());
+
+      let error = parse("a\n  $end").unwrap_err();
+      assert_eq!(at(2, 3, 4), error.span());
+    }
+  }
+
+  #[test]
+  fn parse_recover() {
+    let (nodes, errors) =
+      crate::parser::Parser::new("$if a\n$b\n$end\n$end\n$for x: $x\n$//\n").parse_recover();
+    let errors = errors
+      .into_iter()
+      .map(|error| (error.span().line, error.kind))
+      .collect::<Vec<_>>();
+
+    assert_eq!(
+      vec![
+        (1, ParseErrorKind::MissingColon("$if")),
+        (4, ParseErrorKind::Unexpected("$end")),
+        (5, ParseErrorKind::MalformedFor("x".to_string())),
+        (6, ParseErrorKind::UnclosedComment),
+      ],
+      errors
+    );
+    assert!(
+      matches!(&nodes[0], Node::If { arms, .. } if arms[0].condition == Expr::Var("a".to_string()))
+    );
+  }
+
+  mod ifs {
+    use super::*;
+    use crate::expression::ExpressionError;
+
+    fn arm(not: bool, variable: &str, block: Vec<Node>) -> IfArm {
+      let variable = Expr::Var(variable.to_string());
+
+      IfArm {
+        condition: match not {
+          true => Expr::Not(Box::new(variable)),
+          false => variable,
+        },
+        block,
+        span: Span::default(),
+      }
+    }
+
+    fn if_node(arms: Vec<IfArm>, else_block: Option<Vec<Node>>) -> Node {
+      Node::If { arms, else_block }
+    }
+
+    #[test]
+    fn if_one_liner() {
+      assert_eq!(
+        Ok(vec![if_node(
+          vec![arm(false, "variable", vec![var("variable")])],
+          None
+        )]),
+        parse("$if variable: $variable")
+      );
+    }
+
+    #[test]
+    fn if_not() {
+      assert_eq!(
+        Ok(vec![if_node(
+          vec![arm(true, "variable", vec![var("variable")])],
+          None
+        )]),
+        parse("$if !variable: $variable")
+      );
+    }
+
+    #[test]
+    fn if_not_double() {
+      let not_not = IfArm {
+        condition: Expr::Not(Box::new(arm(true, "variable.a", vec![]).condition)),
+        ..arm(false, "", vec![var("variable")])
+      };
+
+      assert_eq!(
+        Ok(vec![if_node(
+          vec![not_not, arm(true, "variable.b", vec![text("b")])],
+          None
+        )]),
+        parse("$if !!variable.a: $variable $elif ! variable.b: b")
+      );
+    }
+
+    #[test]
+    fn if_expression() {
+      let condition = Expr::parse("a.b == \"x: y\" and not c").unwrap();
+
+      assert_eq!(
+        Ok(vec![if_node(
+          vec![IfArm {
+            condition,
+            ..arm(false, "", vec![text("yes")])
+          }],
+          None
+        )]),
+        parse("$if a.b == \"x: y\" and not c: yes")
+      );
+    }
+
+    #[test]
+    fn if_invalid_condition() {
+      let error = crate::parser::parse("$if a == 1 and:\nA\n$end").unwrap_err();
+
+      assert_eq!(
+        ParseErrorKind::InvalidCondition(ExpressionError::UnexpectedEnd),
+        error.kind
+      );
+      assert_eq!(14, error.span().offset);
+      assert_eq!(
+        Err(ParseErrorKind::InvalidCondition(
+          ExpressionError::Unexpected("b".to_string())
+        )),
+        parse("$if a: A $elif a b: B")
+      );
+    }
+
+    #[test]
+    fn if_nested() {
+      assert_eq!(
+        Ok(vec![if_node(
+          vec![arm(
+            false,
+            "variable.a",
+            vec![if_node(
+              vec![arm(false, "variable.b", vec![var("variable.b")])],
+              None
+            )]
+          )],
+          None
+        )]),
+        parse("$if variable.a: $if variable.b: $variable.b")
+      );
+    }
+
+    #[test]
+    fn if_multiline() {
+      assert_eq!(
+        Ok(vec![if_node(
+          vec![arm(
+            false,
+            "variable.a",
+            vec![if_node(
+              vec![arm(
+                false,
+                "variable.b",
+                vec![var("variable.b"), text("\n")]
+              )],
+              None
+            )]
+          )],
+          None
+        )]),
+        parse("$if variable.a: \n$if variable.b: \n$variable.b\n$end\n$end")
+      );
+    }
+
+    #[test]
+    fn if_else_one_liner() {
+      assert_eq!(
+        Ok(vec![if_node(
+          vec![arm(false, "variable", vec![var("variable")])],
+          Some(vec![text("nothing")])
+        )]),
+        parse("$if variable: $variable $else: nothing")
+      );
+    }
+
+    #[test]
+    fn if_else_multiline() {
+      assert_eq!(
+        Ok(vec![if_node(
+          vec![arm(
+            false,
+            "a",
+            vec![
+              if_node(vec![arm(false, "b", vec![var("b")])], Some(vec![text("c")])),
+              text("\n")
+            ]
+          )],
+          Some(vec![if_node(
+            vec![arm(false, "d", vec![text("d\n")])],
+            None
+          )])
+        )]),
+        parse("$if a:\n$if b: $b $else: c\n$else:\n$if d:\nd\n$end\n$end\n")
+      );
+    }
+
+    #[test]
+    fn elif_one_liner() {
+      assert_eq!(
+        Ok(vec![if_node(
+          vec![
+            arm(false, "a", vec![text("A")]),
+            arm(false, "b", vec![text("B")]),
+            arm(false, "c", vec![text("C")])
+          ],
+          Some(vec![text("D")])
+        )]),
+        parse("$if a: A $elif b: B $else if c: C $else: D")
+      );
+    }
+
+    #[test]
+    fn elif_multiline() {
+      assert_eq!(
+        Ok(vec![
+          if_node(
+            vec![
+              arm(
+                false,
+                "a",
+                vec![
+                  if_node(
+                    vec![
+                      arm(false, "x", vec![text("X")]),
+                      arm(false, "y", vec![text("Y")])
+                    ],
+                    None
+                  ),
+                  text("\n")
+                ]
+              ),
+              arm(false, "b", vec![text("B\n")]),
+              arm(false, "c", vec![text("C\n")])
+            ],
+            None
+          ),
+          text("after")
+        ]),
+        parse("$if a:\n$if x: X $elif y: Y\n$elif b:\nB\n$else if c:\nC\n$end\nafter")
+      );
+    }
+
+    #[test]
+    fn if_no_end() {
+      assert_eq!(
+        Err(ParseErrorKind::UnclosedBlock("$if")),
+        parse("$if a:\nA\n")
+      );
+      assert_eq!(Err(ParseErrorKind::UnclosedBlock("$if")), parse("$if a:"));
+      assert_eq!(
+        Err(ParseErrorKind::UnclosedBlock("$if")),
+        parse("$if a: a $else:\nb\n")
+      );
+    }
+
+    #[test]
+    fn if_missing_colon() {
+      assert_eq!(
+        Err(ParseErrorKind::MissingColon("$if")),
+        parse("$if a\nA\n$end")
+      );
+      assert_eq!(
+        Err(ParseErrorKind::MissingColon("$elif")),
+        parse("$if a: A $elif b")
+      );
+      assert_eq!(
+        Err(ParseErrorKind::MissingColon("$for")),
+        parse("$for a in b")
+      );
+    }
+
+    #[test]
+    fn if_missing_condition() {
+      assert_eq!(
+        Err(ParseErrorKind::MissingCondition("$if")),
+        parse("$if : a")
+      );
+      assert_eq!(
+        Err(ParseErrorKind::MissingCondition("$elif")),
+        parse("$if a: a $elif !: b")
+      );
+    }
+
+    #[test]
+    fn if_double_else() {
+      assert_eq!(
+        Err(ParseErrorKind::Unexpected("$else")),
+        parse("$if a: a $else: b $else: c")
+      );
+    }
+
+    #[test]
+    fn stray_end() {
+      assert_eq!(Err(ParseErrorKind::Unexpected("$end")), parse("a $end"));
+    }
+  }
+
+  mod fors {
+    use super::*;
+    use crate::context::Value;
+    use crate::expression::{BinaryOp, ExpressionError};
+
+    fn for_node(name: &str, variable: &str, block: Vec<Node>) -> Node {
+      Node::For {
+        key: None,
+        name: name.to_string(),
+        iterable: Iterable::Variable(variable.to_string()),
+        condition: None,
+        block,
+        else_block: None,
+        span: Span::default(),
+      }
+    }
+
+    #[test]
+    fn for_one_liner() {
+      assert_eq!(
+        Ok(vec![
+          text("1 "),
+          for_node("n", "nums", vec![var("n"), text(" ")]),
+          text("5")
+        ]),
+        parse("1 $for n in nums: $n  $end 5")
+      );
+    }
+
+    #[test]
+    fn for_multiline() {
+      assert_eq!(
+        Ok(vec![for_node(
+          "item",
+          "items",
+          vec![
+            text("- ["),
+            var("item.name"),
+            text("]("),
+            var("item.url"),
+            text(")\n")
+          ]
+        )]),
+        parse("$for item in items:\n- [$item.name]($item.url)\n$end\n")
+      );
+    }
+
+    #[test]
+    fn for_nested() {
+      assert_eq!(
+        Ok(vec![for_node(
+          "a",
+          "list",
+          vec![for_node("b", "a", vec![var("b")])]
+        )]),
+        parse("$for a in list: $for b in a: $b")
+      );
+    }
+
+    #[test]
+    fn for_key_value() {
+      assert_eq!(
+        Ok(vec![Node::For {
+          key: Some("key".to_string()),
+          name: "value".to_string(),
+          iterable: Iterable::Variable("object".to_string()),
+          condition: None,
+          block: vec![var("key"), var("value")],
+          else_block: None,
+          span: Span::default(),
+        }]),
+        parse("$for key , value in object: $key$value")
+      );
+    }
+
+    #[test]
+    fn for_else() {
+      let for_else = |block: Vec<Node>, else_block: &str| Node::For {
+        key: None,
+        name: "a".to_string(),
+        iterable: Iterable::Variable("b".to_string()),
+        condition: None,
+        block,
+        else_block: Some(vec![text(else_block)]),
+        span: Span::default(),
+      };
+
+      assert_eq!(
+        Ok(vec![for_else(vec![var("a")], "none")]),
+        parse("$for a in b: $a $else: none")
+      );
+      assert_eq!(
+        Ok(vec![
+          for_else(vec![var("a"), text("\n")], "none\n"),
+          text("after")
+        ]),
+        parse("$for a in b:\n$a\n$empty:\nnone\n$end\nafter")
+      );
+      assert_eq!(Ok(vec![var("empty"), text(" a")]), parse("$empty a"));
+    }
+
+    #[test]
+    fn for_invalid_branches() {
+      assert_eq!(
+        Err(ParseErrorKind::Unexpected("$elif")),
+        parse("$for a in b: $a $elif c: c")
+      );
+      assert_eq!(
+        Err(ParseErrorKind::Unexpected("$empty")),
+        parse("$if a: a $empty: b")
+      );
+      assert_eq!(
+        Err(ParseErrorKind::Unexpected("$empty")),
+        parse("$for a in b: $a $else: none $empty: none")
+      );
+    }
+
+    #[test]
+    fn for_invalid() {
+      assert_eq!(
+        Err(ParseErrorKind::MalformedFor("a".to_string())),
+        parse("$for a: $a")
+      );
+      assert_eq!(
+        Err(ParseErrorKind::MalformedFor("a b in c".to_string())),
+        parse("$for a b in c: $a")
+      );
+      assert_eq!(
+        Err(ParseErrorKind::MalformedFor("a in".to_string())),
+        parse("$for a in: $a")
+      );
+      assert_eq!(
+        Err(ParseErrorKind::MalformedFor("a, in b".to_string())),
+        parse("$for a, in b: $a")
+      );
+    }
+
+    #[test]
+    fn for_condition() {
+      assert_eq!(
+        Ok(vec![Node::For {
+          key: None,
+          name: "a".to_string(),
+          iterable: Iterable::Variable("b".to_string()),
+          condition: Some(Expr::Binary {
+            op: BinaryOp::Gt,
+            left: Box::new(Expr::Var("a".to_string())),
+            right: Box::new(Expr::Literal(Value::Int(1))),
+          }),
+          block: vec![var("a")],
+          else_block: None,
+          span: Span::default(),
+        }]),
+        parse("$for a in b if a > 1: $a")
+      );
+      assert_eq!(
+        Err(ParseErrorKind::MissingCondition("$for")),
+        parse("$for a in b if : $a")
+      );
+      assert!(matches!(
+        parse("$for a in b if a >: $a"),
+        Err(ParseErrorKind::InvalidCondition(_))
+      ));
+    }
+
+    #[test]
+    fn break_continue() {
+      assert_eq!(
+        Ok(vec![for_node(
+          "a",
+          "b",
+          vec![Node::If {
+            arms: vec![IfArm {
+              condition: Expr::Var("a".to_string()),
+              block: vec![Node::Break],
+              span: Span::default(),
+            }],
+            else_block: Some(vec![Node::Continue, text("\n")]),
+          }]
+        )]),
+        parse("$for a in b:\n$if a: $break $else:\n$continue\n$end\n$end")
+      );
+      assert_eq!(
+        Err(ParseErrorKind::OutsideLoop("$break")),
+        parse("$if a: $break")
+      );
+      assert_eq!(
+        Err(ParseErrorKind::OutsideLoop("$continue")),
+        parse("$for a in b: $a $else: $continue")
+      );
+    }
+
+    #[test]
+    fn for_range() {
+      let range = |start: Expr, end: Expr, step: Option<Expr>, inclusive: bool| {
+        Ok(vec![Node::For {
+          key: None,
+          name: "i".to_string(),
+          iterable: Iterable::Range {
+            start: Box::new(start),
+            end: Box::new(end),
+            step: step.map(Box::new),
+            inclusive,
+          },
+          condition: None,
+          block: vec![var("i")],
+          else_block: None,
+          span: Span::default(),
+        }])
+      };
+      let int = |int| Expr::Literal(Value::Int(int));
+
+      assert_eq!(
+        range(int(1), Expr::Var("n".to_string()), None, false),
+        parse("$for i in 1..n: $i")
+      );
+      assert_eq!(
+        range(int(1), int(10), None, true),
+        parse("$for i in 1 ..= 10: $i")
+      );
+      assert_eq!(
+        range(int(0), int(5), None, false),
+        parse("$for i in range(5): $i")
+      );
+      assert_eq!(
+        range(int(10), int(0), Some(int(-2)), false),
+        parse("$for i in range(10, 0, -2): $i")
+      );
+      assert_eq!(
+        Ok(vec![for_node("i", "ranges", vec![var("i")])]),
+        parse("$for i in ranges: $i")
+      );
+    }
+
+    #[test]
+    fn for_quoted_path() {
+      assert_eq!(
+        Ok(vec![for_node("x", "map[\"a..b\"]", vec![var("x")])]),
+        parse("$for x in map[\"a..b\"]: $x")
+      );
+      assert_eq!(
+        Ok(vec![for_node("x", "map[\"a if b\"]", vec![var("x")])]),
+        parse("$for x in map[\"a if b\"]: $x")
+      );
+
+      let Ok(nodes) = parse("$for x in map['a in b'][k] if x != \" if \": $x") else {
+        panic!("expected a $for");
+      };
+      assert!(matches!(
+        &nodes[0],
+        Node::For { iterable: Iterable::Variable(variable), condition: Some(_), .. }
+          if variable == "map['a in b'][k]"
+      ));
+    }
+
+    #[test]
+    fn for_range_invalid() {
+      assert_eq!(
+        Err(ParseErrorKind::RangeArguments(0)),
+        parse("$for i in range(): $i")
+      );
+      assert_eq!(
+        Err(ParseErrorKind::RangeArguments(4)),
+        parse("$for i in range(1, 2, 3, 4): $i")
+      );
+      assert_eq!(
+        Err(ParseErrorKind::InvalidRange(ExpressionError::UnexpectedEnd)),
+        parse("$for i in 1..: $i")
+      );
+    }
+
+    #[test]
+    fn for_no_end() {
+      assert_eq!(
+        Err(ParseErrorKind::UnclosedBlock("$for")),
+        parse("$for a in b:\n$a")
+      );
+    }
+  }
+}
@@ -0,0 +1,140 @@
+use std::borrow::Cow;
+
+use crate::expression::{scan, split_string};
+
+/// A step in a path like `items[0].name`, `map["key with spaces"]` or `map[other.var]`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment<'a> {
+  /// `name` or `["name"]`
+  Key(Cow<'a, str>),
+  /// `[0]`, or `[-1]` to count from the end
+  Index(i64),
+  /// `[other.var]`, the key or index is the value of another path
+  Dynamic(&'a str),
+}
+
+/// Splits a path into its segments, `None` if it's malformed
+pub fn parse(path: &str) -> Option<Vec<Segment<'_>>> {
+  let mut segments = Vec::new();
+  let mut rest = path;
+  let mut first = true;
+
+  while first || !rest.is_empty() {
+    if let Some(brackets) = rest.strip_prefix('[') {
+      let (inner, after) = split_brackets(brackets)?;
+      segments.push(bracket_segment(inner.trim())?);
+      rest = after;
+    } else {
+      let name = match first {
+        true => rest,
+        false => rest.strip_prefix('.')?,
+      };
+      let end = name.find(['.', '[']).unwrap_or(name.len());
+
+      segments.push(Segment::Key(Cow::Borrowed(&name[..end])));
+      rest = &name[end..];
+    }
+
+    first = false;
+  }
+
+  Some(segments)
+}
+
+/// Length of the path at the start of `source`,
+/// a trailing `.` or a `[` that's never closed isn't part of it
+pub fn path_len(source: &str) -> usize {
+  let is_name = |c: char| c.is_alphanumeric() || c == '_';
+  let mut len = 0;
+
+  loop {
+    let rest = &source[len..];
+
+    if let Some((inner, _)) = rest.strip_prefix('[').and_then(split_brackets) {
+      len += inner.len() + 2;
+      continue;
+    }
+
+    let name = match len {
+      0 => rest,
+      _ => match rest.strip_prefix('.') {
+        Some(name) if name.starts_with(is_name) => name,
+        _ => return len,
+      },
+    };
+    let name_len = name.find(|c| !is_name(c)).unwrap_or(name.len());
+
+    if name_len == 0 {
+      return len;
+    }
+
+    len += rest.len() - name.len() + name_len;
+  }
+}
+
+/// Splits the inside of `[...]` off of `source`, which starts right after the `[`,
+/// skipping over quoted strings and nested brackets, `None` if it's never closed on the same line
+fn split_brackets(source: &str) -> Option<(&str, &str)> {
+  let line = &source[..source.find('\n').unwrap_or(source.len())];
+  let end = scan(line, |rest, depth| depth == 0 && rest.starts_with(']'))?;
+
+  Some((&source[..end], &source[end + 1..]))
+}
+
+fn bracket_segment(inner: &str) -> Option<Segment<'_>> {
+  if inner.starts_with(['"', '\'']) {
+    return match split_string(inner)? {
+      (key, len) if len == inner.len() => Some(Segment::Key(Cow::Owned(key))),
+      _ => None,
+    };
+  }
+
+  match inner.parse() {
+    Ok(index) => Some(Segment::Index(index)),
+    Err(_) if inner.is_empty() => None,
+    Err(_) => Some(Segment::Dynamic(inner)),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::borrow::Cow;
+
+  use crate::path::{parse, path_len, Segment};
+
+  fn key(key: &str) -> Segment<'_> {
+    Segment::Key(Cow::Borrowed(key))
+  }
+
+  #[test]
+  fn segments() {
+    assert_eq!(Some(vec![key("a"), key("b")]), parse("a.b"));
+    assert_eq!(
+      Some(vec![key("items"), Segment::Index(-1), key("name")]),
+      parse("items[-1].name")
+    );
+    assert_eq!(
+      Some(vec![key("map"), key("key with spaces.and dots")]),
+      parse("map[\"key with spaces.and dots\"]")
+    );
+    assert_eq!(
+      Some(vec![key("map"), Segment::Dynamic("list[0].key")]),
+      parse("map[ list[0].key ]")
+    );
+    assert_eq!(None, parse("a[0"));
+    assert_eq!(None, parse("a[]"));
+    assert_eq!(None, parse("a[\"b\" c]"));
+    assert_eq!(None, parse("a[0]b"));
+  }
+
+  #[test]
+  fn length() {
+    assert_eq!(1, path_len("a"));
+    assert_eq!(3, path_len("a.b."));
+    assert_eq!(13, path_len("items[0].name, and"));
+    assert_eq!(15, path_len("map[\"a ] b\"][i] c"));
+    assert_eq!(4, path_len("list[0"));
+    assert_eq!(4, path_len("list.[0]"));
+    assert_eq!(4, path_len("name..."));
+  }
+}
@@ -1,211 +1,1707 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::Write;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use itertools::Itertools;
 use thiserror::Error;
 
-use crate::context::{Context, GetValue, SetValue};
+use crate::context::{Context, ContextBuilder, GetValue, Scope, SetValue, Value};
+use crate::diagnostic::Diagnostic;
+use crate::environment::Environment;
+use crate::expression::{self, EvalError, Expr, FilterCall, State};
+use crate::loader::LoadError;
+use crate::parser::{Iterable, Node, Param, ParseError};
+use crate::span::{Snippet, Span};
+use crate::template::Template;
 
 #[derive(Error, Debug)]
 #[error("{}")]
 pub enum Error {
-  #[error("variable not found: {0}")]
-  VariableNotFound(String),
+  #[error("variable not found: {0}\n{1}")]
+  VariableNotFound(String, Snippet),
+  /// a condition or filter that couldn't be evaluated
+  #[error("{0}\n{1}")]
+  Eval(Box<EvalError>, Snippet),
+  #[error("can't loop over {1}: {0}\n{2}")]
+  NotIterable(String, &'static str, Snippet),
+  /// a template that couldn't be loaded by name
+  #[error("{0}")]
+  Template(Box<LoadError>),
+  /// an `$include` or `$extends` that couldn't be loaded
+  #[error("{0}\n{1}")]
+  Load(Box<LoadError>, Snippet),
+  /// an `$include` or `$extends` of a template that's already being rendered
+  #[error("recursive {0}: {1}\n{2}")]
+  Recursive(&'static str, String, Snippet),
+  /// a `$macro` call or `$include` nested in more than `MAX_DEPTH` blocks, macros and includes,
+  /// usually a macro that never stops calling itself
+  #[error("{0} nested more than {1} levels deep\n{2}")]
+  TooDeep(String, usize, Snippet),
+  #[error("no parent $block {0} for $super()\n{1}")]
+  NoParentBlock(String, Snippet),
+  #[error("{0}")]
+  Parse(#[from] ParseError),
   #[error("{0}")]
   Io(#[from] std::io::Error),
   #[error("{0}")]
   FromUtf8(#[from] std::string::FromUtf8Error),
 }
 
+impl Error {
+  /// Where in the template the error happened, if anywhere
+  pub fn span(&self) -> Option<Span> {
+    match self {
+      Error::VariableNotFound(_, snippet)
+      | Error::NotIterable(_, _, snippet)
+      | Error::Eval(_, snippet)
+      | Error::Load(_, snippet)
+      | Error::Recursive(_, _, snippet)
+      | Error::TooDeep(_, _, snippet)
+      | Error::NoParentBlock(_, snippet) => Some(snippet.span),
+      Error::Parse(error) => Some(error.span()),
+      Error::Template(_) | Error::Io(_) | Error::FromUtf8(_) => None,
+    }
+  }
+}
+
+/// How many blocks, macros and includes can be nested in each other, so a macro that keeps
+/// calling itself stops with an error instead of overflowing the stack
+const MAX_DEPTH: usize = 100;
+
+/// Elements of whatever a `$for` loops over, along with their index or key
+type Entries<'a> = Box<dyn ExactSizeIterator<Item = (Value, Cow<'a, Value>)> + 'a>;
+
+/// What to do after rendering a block, `$break` and `$continue` stop it early
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Flow {
+  Next,
+  Break,
+  Continue,
+}
+
+/// How the renderer got to the template it's rendering, through `$include`s and `$extends`
+#[derive(Debug, Clone, Default)]
+struct Origin<'a> {
+  /// names of the templates that included this one, to catch an `$include` of itself
+  includes: Vec<String>,
+  /// the template that was rendered followed by every template it extends, empty without `$extends`
+  chain: Rc<[&'a Template]>,
+  /// the `$block` being rendered and the index in `chain` of the template it's from, for `$super()`
+  block: Option<(String, usize)>,
+  /// how many blocks, macros and includes are being rendered, up to `MAX_DEPTH`
+  depth: usize,
+}
+
+/// A `$macro` along with the template it's from
+#[derive(Clone, Copy)]
+struct Macro<'a> {
+  template: &'a Template,
+  params: &'a [Param],
+  block: &'a [Node],
+}
+
 pub struct Renderer<'a, W> {
-  context: Context,
-  source: &'a str,
+  environment: &'a Environment,
+  context: &'a Context,
+  template: &'a Template,
   writer: W,
+  /// only collected when checking the template
+  diagnostics: Option<Vec<Diagnostic>>,
+  origin: Origin<'a>,
+  /// whether conditions have to be bools, in every template this render gets to
+  strict: bool,
 }
 
 impl<'a, W> Renderer<'a, W>
 where
   W: std::io::Write,
 {
-  pub fn new(context: Context, source: &'a str, writer: W) -> Self {
+  pub fn new(
+    environment: &'a Environment,
+    context: &'a Context,
+    template: &'a Template,
+    writer: W,
+  ) -> Self {
     Self {
+      environment,
       context,
-      source,
+      template,
+      writer,
+      diagnostics: None,
+      origin: Origin::default(),
+      strict: environment.is_strict() || template.is_strict(),
+    }
+  }
+
+  /// When checking, records the error and keeps going, otherwise returns it
+  fn report(&mut self, error: Error) -> Result<(), Error> {
+    match &mut self.diagnostics {
+      Some(diagnostics) => {
+        diagnostics.push(error.into());
+        Ok(())
+      }
+      None => Err(error),
+    }
+  }
+
+  fn snippet(&self, span: Span) -> Snippet {
+    snippet(self.template, span)
+  }
+
+  fn variable_not_found(&self, name: &str, span: Span) -> Error {
+    Error::VariableNotFound(name.to_string(), self.snippet(span))
+  }
+
+  fn eval_error(&self, error: EvalError, span: Span) -> Error {
+    Error::Eval(Box::new(error), self.snippet(span))
+  }
+
+  fn state<'b>(&'b self, scope: &'b Scope<'b>) -> State<'b> {
+    State {
+      environment: self.environment,
+      context: scope,
+      strict: self.strict,
+    }
+  }
+
+  /// A missing variable is an error, unless it starts with a `default` filter
+  fn render_var(
+    &mut self,
+    scope: &Scope,
+    name: &str,
+    filters: &[FilterCall],
+    span: Span,
+  ) -> Result<(), Error> {
+    let value = match scope.get_value(name) {
+      Some(value) => Cow::Borrowed(value),
+      None
+        if filters
+          .first()
+          .is_some_and(|filter| filter.name == "default") =>
+      {
+        Cow::Owned(Value::Null)
+      }
+      None => return self.report(self.variable_not_found(name, span)),
+    };
+
+    self.render_value(scope, value, filters, span)
+  }
+
+  fn render_call(
+    &mut self,
+    scope: &Scope,
+    name: &str,
+    args: &[Expr],
+    filters: &[FilterCall],
+    span: Span,
+  ) -> Result<(), Error> {
+    if name == "super" && args.is_empty() && self.origin.block.is_some() {
+      return self.render_super(scope, filters, span);
+    }
+
+    if let Some(definition) = self.find_macro(name) {
+      return self.render_macro(scope, name, definition, args, filters, span);
+    }
+
+    match expression::call(name, args, &self.state(scope)) {
+      Ok(value) => self.render_value(scope, Cow::Owned(value), filters, span),
+      Err(error) => self.report(self.eval_error(error, span)),
+    }
+  }
+
+  fn render_value(
+    &mut self,
+    scope: &Scope,
+    mut value: Cow<Value>,
+    filters: &[FilterCall],
+    span: Span,
+  ) -> Result<(), Error> {
+    for filter in filters {
+      match filter.apply(&value, &self.state(scope)) {
+        Ok(filtered) => value = Cow::Owned(filtered),
+        Err(error) => return self.report(self.eval_error(error, span)),
+      }
+    }
+
+    Ok(write!(self.writer, "{value}")?)
+  }
+
+  /// Finds a `$macro` in this template, then in the templates it extends and then in the environment
+  fn find_macro(&self, name: &str) -> Option<Macro<'a>> {
+    [self.template]
+      .into_iter()
+      .chain(self.origin.chain.iter().copied())
+      .chain(self.environment.macro_template(name))
+      .find_map(|template| {
+        template.nodes().iter().find_map(|node| match node {
+          Node::Macro {
+            name: macro_name,
+            params,
+            block,
+            ..
+          } if macro_name == name => Some(Macro {
+            template,
+            params,
+            block,
+          }),
+          _ => None,
+        })
+      })
+  }
+
+  /// Renders a `$macro` with only the variables the template was rendered with and its arguments,
+  /// missing arguments take their default, which is evaluated where it's called
+  fn render_macro(
+    &mut self,
+    scope: &Scope,
+    name: &str,
+    definition: Macro,
+    args: &[Expr],
+    filters: &[FilterCall],
+    span: Span,
+  ) -> Result<(), Error> {
+    if self.origin.depth >= MAX_DEPTH {
+      let error = Error::TooDeep(format!("${name}()"), MAX_DEPTH, self.snippet(span));
+      return self.report(error);
+    }
+
+    if args.len() > definition.params.len() {
+      let error =
+        EvalError::TooManyArguments(name.to_string(), definition.params.len(), args.len());
+      return self.report(self.eval_error(error, span));
+    }
+
+    let state = self.state(scope);
+    let values = definition
+      .params
+      .iter()
+      .enumerate()
+      .map(|(index, param)| match (args.get(index), &param.default) {
+        (Some(arg), _) => arg.eval(&state),
+        (None, Some(default)) => default.eval(&state),
+        (None, None) => Err(EvalError::MissingArgument(
+          name.to_string(),
+          param.name.clone(),
+        )),
+      })
+      .collect::<Result<Vec<_>, _>>();
+
+    let values = match values {
+      Ok(values) => values,
+      Err(error) => return self.report(self.eval_error(error, span)),
+    };
+
+    let mut macro_scope = Scope::new(self.context);
+
+    for (param, value) in definition.params.iter().zip(values) {
+      macro_scope.set_value(&param.name, value);
+    }
+
+    let origin = Origin {
+      includes: self.origin.includes.clone(),
+      depth: self.origin.depth,
+      ..Origin::default()
+    };
+
+    // only rendered into a buffer when there are filters to pass the output through
+    let (template, block) = (definition.template, definition.block);
+    let mut buffer = Vec::new();
+    let buffered = !filters.is_empty();
+
+    self.render_other(
+      &macro_scope,
+      template,
+      block,
+      origin,
+      buffered.then_some(&mut buffer),
+    )?;
+
+    if !buffered {
+      return Ok(());
+    }
+
+    let value = Value::String(String::from_utf8(buffer)?);
+    self.render_value(scope, Cow::Owned(value), filters, span)
+  }
+
+  /// Renders `nodes` of another template, it shares the output and, when checking, the diagnostics,
+  /// unless `buffer` is given to render into instead, variables it sets are gone afterwards
+  fn render_other(
+    &mut self,
+    scope: &Scope,
+    template: &Template,
+    nodes: &[Node],
+    origin: Origin,
+    buffer: Option<&mut Vec<u8>>,
+  ) -> Result<Flow, Error> {
+    let writer: &mut dyn Write = match buffer {
+      Some(buffer) => buffer,
+      None => &mut self.writer,
+    };
+    let mut renderer = Renderer {
+      environment: self.environment,
+      context: self.context,
+      template,
       writer,
+      diagnostics: self.diagnostics.take(),
+      origin,
+      strict: self.strict,
+    };
+
+    let result = renderer._render(&mut scope.child(), nodes);
+    self.diagnostics = renderer.diagnostics;
+
+    result
+  }
+
+  /// Loads a template for an `$include` or `$extends` in `template`, parse errors point into the loaded template
+  fn load(
+    &mut self,
+    template: &Template,
+    name: &str,
+    span: Span,
+  ) -> Result<Option<Arc<Template>>, Error> {
+    match self.environment.get_template(name) {
+      Ok(template) => Ok(Some(template)),
+      Err(LoadError::Parse(error)) => self.report(Error::Parse(error)).map(|_| None),
+      Err(error) => self
+        .report(Error::Load(Box::new(error), snippet(template, span)))
+        .map(|_| None),
     }
   }
 
-  fn _render(&mut self, context: Context, text: &'a str) -> Result<(), Error> {
-    for token in TokenIter::new(text) {
-      match token {
-        Item::Normal(text) => write!(self.writer, "{text}")?,
-        Item::Var(path) => {
-          let value = context
-            .get_string(path)
-            .ok_or_else(|| Error::VariableNotFound(path.to_string()))?;
+  /// Renders a template from the environment's loader with the same variables
+  fn render_include(&mut self, scope: &Scope, name: &str, span: Span) -> Result<(), Error> {
+    let mut includes = self.origin.includes.clone();
+    includes.extend(self.template.name().map(str::to_string));
+
+    if includes.iter().any(|include| include == name) {
+      let chain = includes
+        .iter()
+        .map(String::as_str)
+        .chain([name])
+        .join(" -> ");
+      return self.report(Error::Recursive("$include", chain, self.snippet(span)));
+    }
 
-          write!(self.writer, "{value}")?;
+    if self.origin.depth >= MAX_DEPTH {
+      let error = Error::TooDeep(format!("$include {name}"), MAX_DEPTH, self.snippet(span));
+      return self.report(error);
+    }
+
+    let Some(template) = self.load(self.template, name, span)? else {
+      return Ok(());
+    };
+    let origin = Origin {
+      includes,
+      depth: self.origin.depth,
+      ..Origin::default()
+    };
+
+    self
+      .render_other(scope, &template, template.nodes(), origin, None)
+      .map(|_| ())
+  }
+
+  /// Renders the template at the end of the `$extends` chain, its `$block`s are taken
+  /// from the first template in the chain that has them, starting with this one
+  fn render_extends(&mut self, scope: &Scope, name: &str, span: Span) -> Result<(), Error> {
+    let mut parents: Vec<Arc<Template>> = Vec::new();
+    let mut next = Some((name.to_string(), span));
+
+    while let Some((name, span)) = next {
+      let template = parents.last().map_or(self.template, Arc::as_ref);
+      let names: Vec<&str> = [self.template]
+        .iter()
+        .copied()
+        .chain(parents.iter().map(Arc::as_ref))
+        .filter_map(Template::name)
+        .collect();
+
+      if names.contains(&name.as_str()) {
+        let chain = names.iter().chain([&name.as_str()]).join(" -> ");
+        return self.report(Error::Recursive("$extends", chain, snippet(template, span)));
+      }
+
+      let Some(parent) = self.load(template, &name, span)? else {
+        return Ok(());
+      };
+
+      next = find_extends(parent.nodes());
+      parents.push(parent);
+    }
+
+    let chain: Rc<[&Template]> = [self.template]
+      .into_iter()
+      .chain(parents.iter().map(Arc::as_ref))
+      .collect();
+    let base = chain[chain.len() - 1];
+    let mut includes = self.origin.includes.clone();
+    includes.extend(self.template.name().map(str::to_string));
+    let origin = Origin {
+      includes,
+      chain,
+      block: None,
+      depth: self.origin.depth,
+    };
+
+    self
+      .render_other(scope, base, base.nodes(), origin, None)
+      .map(|_| ())
+  }
+
+  /// Renders the first `$block` called `name` in the `$extends` chain, starting at `level`,
+  /// `None` if none of the templates from there on have it
+  fn render_block(
+    &mut self,
+    scope: &Scope,
+    name: &str,
+    level: usize,
+    buffer: Option<&mut Vec<u8>>,
+  ) -> Result<Option<Flow>, Error> {
+    let chain = self.origin.chain.clone();
+    let found = chain
+      .iter()
+      .enumerate()
+      .skip(level)
+      .find_map(|(index, template)| Some((index, *template, find_block(template.nodes(), name)?)));
+
+    let Some((index, template, block)) = found else {
+      return Ok(None);
+    };
+    let origin = Origin {
+      includes: self.origin.includes.clone(),
+      chain,
+      block: Some((name.to_string(), index)),
+      depth: self.origin.depth,
+    };
+
+    self
+      .render_other(scope, template, block, origin, buffer)
+      .map(Some)
+  }
+
+  /// `$super()` renders the parent of the `$block` it's in
+  fn render_super(
+    &mut self,
+    scope: &Scope,
+    filters: &[FilterCall],
+    span: Span,
+  ) -> Result<(), Error> {
+    let Some((block, level)) = self.origin.block.clone() else {
+      return Ok(());
+    };
+    let mut buffer = Vec::new();
+
+    match self.render_block(scope, &block, level + 1, Some(&mut buffer))? {
+      Some(_) => {
+        let value = Value::String(String::from_utf8(buffer)?);
+        self.render_value(scope, Cow::Owned(value), filters, span)
+      }
+      None => self.report(Error::NoParentBlock(block, self.snippet(span))),
+    }
+  }
+
+  /// Evaluates the bounds of a range, which are only evaluated once before looping
+  fn range_entries<'b>(
+    &self,
+    scope: &Scope,
+    start: &Expr,
+    end: &Expr,
+    step: Option<&Expr>,
+    inclusive: bool,
+  ) -> Result<Entries<'b>, EvalError> {
+    let state = self.state(scope);
+    let int = |expr: &Expr| match expr.eval(&state)? {
+      Value::Int(int) => Ok(int),
+      Value::UInt(uint) => i64::try_from(uint).map_err(|_| EvalError::RangeOverflow(uint)),
+      value => Err(EvalError::NotInt(value.type_name())),
+    };
+
+    let (start, end) = (int(start)?, int(end)?);
+    let step = step.map(int).transpose()?.unwrap_or(1);
+
+    if step == 0 {
+      return Err(EvalError::ZeroStep);
+    }
+
+    let length = range_length(start, end, step, inclusive);
+
+    Ok(Box::new((0..length).map(move |index| {
+      let value = i128::from(start) + index as i128 * i128::from(step);
+      (
+        Value::UInt(index as u64),
+        Cow::Owned(Value::Int(value as i64)),
+      )
+    })))
+  }
+
+  /// Elements of whatever a `$for` loops over, and whether it's an object
+  fn entries<'b>(
+    &self,
+    scope: &'b Scope,
+    iterable: &'b Iterable,
+    span: Span,
+  ) -> Result<(Entries<'b>, bool), Error> {
+    match iterable {
+      Iterable::Variable(variable) => match scope.get_value(variable) {
+        Some(Value::List(list)) => Ok((Box::new(list_entries(list)), false)),
+        Some(Value::Object(object)) => Ok((Box::new(object_entries(object)), true)),
+        Some(value) => Err(Error::NotIterable(
+          variable.clone(),
+          value.type_name(),
+          self.snippet(span),
+        )),
+        None => Err(self.variable_not_found(variable, span)),
+      },
+      Iterable::Range {
+        start,
+        end,
+        step,
+        inclusive,
+      } => self
+        .range_entries(scope, start, end, step.as_deref(), *inclusive)
+        .map(|entries| (entries, false))
+        .map_err(|error| self.eval_error(error, span)),
+    }
+  }
+
+  fn _render(&mut self, scope: &mut Scope, nodes: &[Node]) -> Result<Flow, Error> {
+    self.origin.depth += 1;
+    let flow = self.render_nodes(scope, nodes);
+    self.origin.depth -= 1;
+
+    flow
+  }
+
+  fn render_nodes(&mut self, scope: &mut Scope, nodes: &[Node]) -> Result<Flow, Error> {
+    for node in nodes {
+      match node {
+        Node::Text(text) => self.writer.write_all(text.as_bytes())?,
+        Node::Comment(_) | Node::Macro { .. } => {}
+        Node::Include { name, span } => self.render_include(scope, name, *span)?,
+        // the rest of a template that extends another one is only there for its `$block`s
+        Node::Extends { name, span } => {
+          self.render_extends(scope, name, *span)?;
+          return Ok(Flow::Next);
         }
-        Item::If(path, not, true_block, false_block) => {
-          let condition = context.get_bool(path);
+        Node::Block { name, block, .. } => {
+          let flow = match self.origin.chain.is_empty() {
+            true => self._render(&mut scope.child(), block)?,
+            false => match self.render_block(scope, name, 0, None)? {
+              Some(flow) => flow,
+              None => self._render(&mut scope.child(), block)?,
+            },
+          };
 
-          if condition ^ not {
-            self._render(context.clone(), true_block)?;
-          } else if let Some(false_block) = false_block {
-            self._render(context.clone(), false_block)?;
+          if flow != Flow::Next {
+            return Ok(flow);
           }
         }
-        Item::For(id, path, block) => {
-          let list = self
-            .context
-            .get_list(path)
-            .ok_or_else(|| Error::VariableNotFound(path.to_string()))?;
-
-          for element in list.clone() {
-            let mut context = context.clone();
-            context.set_value(id, element);
-            self._render(context, block)?;
+        Node::Set { name, value, span } => match value.eval(&self.state(scope)) {
+          Ok(value) => {
+            scope.set_value(name, value);
+          }
+          Err(error) => self.report(self.eval_error(error, *span))?,
+        },
+        Node::With {
+          bindings,
+          block,
+          span,
+        } => {
+          let mut scope = scope.child();
+
+          for (name, value) in bindings {
+            // later bindings can use the ones before them
+            let value = match value.eval(&self.state(&scope)) {
+              Ok(value) => value,
+              Err(error) => {
+                self.report(self.eval_error(error, *span))?;
+                Value::Null
+              }
+            };
+
+            scope.set_value(name, value);
+          }
+
+          match self._render(&mut scope, block)? {
+            Flow::Next => {}
+            flow => return Ok(flow),
+          }
+        }
+        Node::Break => return Ok(Flow::Break),
+        Node::Continue => return Ok(Flow::Continue),
+        Node::Var {
+          name,
+          filters,
+          span,
+        } => self.render_var(scope, name, filters, *span)?,
+        Node::Call {
+          name,
+          args,
+          filters,
+          span,
+        } => self.render_call(scope, name, args, filters, *span)?,
+        Node::If { arms, else_block } => {
+          if let Some(diagnostics) = &mut self.diagnostics {
+            for arm in arms {
+              for variable in arm.condition.variables() {
+                if scope.get_value(variable).is_none() {
+                  diagnostics.push(Diagnostic::warning(
+                    format!("variable not found, treated as null: {variable}"),
+                    snippet(self.template, arm.span),
+                  ));
+                }
+              }
+            }
+          }
+
+          let mut block = else_block.as_ref();
+
+          for arm in arms {
+            match arm.condition.eval_bool(&self.state(scope)) {
+              Ok(true) => {
+                block = Some(&arm.block);
+                break;
+              }
+              Ok(false) => {}
+              Err(error) => self.report(self.eval_error(error, arm.span))?,
+            }
+          }
+
+          if let Some(block) = block {
+            match self._render(scope, block)? {
+              Flow::Next => {}
+              flow => return Ok(flow),
+            }
+          }
+        }
+        Node::For {
+          key,
+          name,
+          iterable,
+          condition,
+          block,
+          else_block,
+          span,
+        } => {
+          let (entries, object) = match self.entries(scope, iterable, *span) {
+            Ok(entries) => entries,
+            Err(error) => {
+              self.report(error)?;
+              continue;
+            }
+          };
+
+          // elements borrowed from the scope are bound without cloning them
+          let bind = |entry| {
+            let (entry_key, entry): (Value, _) = entry;
+            let mut scope = scope.child();
+
+            match key {
+              Some(key) => {
+                scope.set_value(key, entry_key);
+                scope.bind(name, entry);
+              }
+              // a single name loops over the keys of an object
+              None if object => {
+                scope.set_value(name, entry_key);
+              }
+              None => scope.bind(name, entry),
+            }
+
+            scope
+          };
+
+          // `loop.length` needs to know how many elements the condition is true for up front,
+          // so they're counted in a pass of their own instead of being collected
+          let length = match condition {
+            Some(condition) => {
+              let mut length = 0;
+
+              for entry in self.entries(scope, iterable, *span)?.0 {
+                match condition.eval_bool(&self.state(&bind(entry))) {
+                  Ok(true) => length += 1,
+                  Ok(false) => {}
+                  Err(error) => self.report(self.eval_error(error, *span))?,
+                }
+              }
+
+              length
+            }
+            None => entries.len(),
+          };
+
+          if length == 0 {
+            if let Some(block) = else_block {
+              match self._render(&mut scope.child(), block)? {
+                Flow::Next => {}
+                flow => return Ok(flow),
+              }
+            }
+          }
+
+          // the same `loop` is used for every element, only its index changes,
+          // so the `loop` of the `$for` it's in is only cloned once
+          let mut loop_object = loop_value(scope.get_value("loop"));
+          let mut index = 0;
+
+          for entry in entries {
+            let mut scope = bind(entry);
+
+            // any errors in the condition were reported while counting
+            if let Some(condition) = condition {
+              if !condition.eval_bool(&self.state(&scope)).unwrap_or(false) {
+                continue;
+              }
+            }
+
+            set_loop_index(&mut loop_object, index, length);
+            scope.bind("loop", Cow::Borrowed(&loop_object));
+            index += 1;
+
+            if self._render(&mut scope, block)? == Flow::Break {
+              break;
+            }
           }
         }
       }
     }
 
-    Ok(())
+    Ok(Flow::Next)
   }
 
   pub fn render(&mut self) -> Result<(), Error> {
-    self._render(self.context.clone(), self.source)
+    // the parser only allows `$break` and `$continue` inside a `$for`
+    self._render(&mut Scope::new(self.context), self.template.nodes())?;
+    Ok(())
+  }
+
+  /// Renders the template, collecting every error instead of stopping at the first one
+  pub fn check(mut self) -> Result<Vec<Diagnostic>, Error> {
+    self.diagnostics = Some(Vec::new());
+    self.render()?;
+
+    Ok(self.diagnostics.unwrap_or_default())
   }
 }
 
-struct TokenIter<'a> {
-  source: &'a str,
-  is_token: bool,
+/// Snippet of `template` at `span`, pointing at the file it was loaded from
+fn snippet(template: &Template, span: Span) -> Snippet {
+  Snippet::new(template.source(), span).with_file(template.name())
 }
 
-impl<'a> TokenIter<'a> {
-  fn new(source: &'a str) -> Self {
-    Self {
-      source,
-      is_token: false,
-    }
-  }
+/// Name and span of the `$extends` of a template, if it has one
+fn find_extends(nodes: &[Node]) -> Option<(String, Span)> {
+  nodes.iter().find_map(|node| match node {
+    Node::Extends { name, span } => Some((name.clone(), *span)),
+    _ => None,
+  })
+}
+
+/// Contents of the `$block` called `name`, wherever it is in the template
+fn find_block<'a>(nodes: &'a [Node], name: &str) -> Option<&'a [Node]> {
+  nodes.iter().find_map(|node| match node {
+    Node::Block {
+      name: block_name,
+      block,
+      ..
+    } if block_name == name => Some(block.as_slice()),
+    node => node
+      .blocks()
+      .into_iter()
+      .find_map(|block| find_block(block, name)),
+  })
+}
+
+/// Elements of a list along with their index
+fn list_entries(list: &[Value]) -> impl ExactSizeIterator<Item = (Value, Cow<'_, Value>)> {
+  list
+    .iter()
+    .enumerate()
+    .map(|(index, element)| (Value::UInt(index as u64), Cow::Borrowed(element)))
+}
+
+/// Values of an object along with their key, sorted by key so the output is always the same
+fn object_entries(
+  object: &HashMap<String, Value>,
+) -> impl ExactSizeIterator<Item = (Value, Cow<'_, Value>)> {
+  object
+    .iter()
+    .sorted_by(|(a, _), (b, _)| a.cmp(b))
+    .map(|(key, value)| (Value::String(key.clone()), Cow::Borrowed(value)))
 }
 
-#[derive(Debug)]
-enum Token<'a> {
-  /// Any other text
-  Normal(&'a str),
-  /// ${0}
-  Var(&'a str),
-  /// $if {1}{0}: ${2} $else: ${3}
-  If(&'a str, bool, &'a str, Option<&'a str>),
-  /// $for {0} in {1}: {2}
-  For(&'a str, &'a str, &'a str),
+/// Number of elements in a range, 0 if the step goes the wrong way
+fn range_length(start: i64, end: i64, step: i64, inclusive: bool) -> usize {
+  let (mut distance, mut step) = (i128::from(end) - i128::from(start), i128::from(step));
+
+  if step < 0 {
+    (distance, step) = (-distance, -step);
+  }
+
+  let length = match inclusive {
+    true if distance >= 0 => distance / step + 1,
+    false if distance > 0 => (distance + step - 1) / step,
+    _ => 0,
+  };
+
+  usize::try_from(length).unwrap_or(usize::MAX)
 }
 
-type Item<'a> = Token<'a>;
+/// The `loop` object of a `$for`, `parent` is the `loop` of the `$for` it's in, if any,
+/// see [set_loop_index] for the rest
+fn loop_value(parent: Option<&Value>) -> Value {
+  let builder = ContextBuilder::new();
+
+  match parent {
+    Some(parent) => builder.set_value("parent", parent.clone()),
+    None => builder,
+  }
+  .build_to_value()
+}
 
-impl<'a> TokenIter<'a> {
-  fn token_start(&mut self) -> Option<Item<'a>> {
-    let token_start = self.source.find('$');
+/// Points the `loop` object of a `$for` at the element at `index`
+fn set_loop_index(loop_value: &mut Value, index: usize, length: usize) {
+  let Value::Object(object) = loop_value else {
+    return;
+  };
+  let fields = [
+    ("index", Value::UInt(index as u64 + 1)),
+    ("index0", Value::UInt(index as u64)),
+    ("revindex", Value::UInt((length - index) as u64)),
+    ("revindex0", Value::UInt((length - index - 1) as u64)),
+    ("first", Value::Bool(index == 0)),
+    ("last", Value::Bool(index + 1 == length)),
+    ("length", Value::UInt(length as u64)),
+  ];
 
-    match token_start {
+  for (name, value) in fields {
+    match object.get_mut(name) {
+      Some(field) => *field = value,
       None => {
-        let source = self.source;
-        self.source = "";
-        Some(Token::Normal(source))
-      }
-      Some(start) => {
-        let source = &self.source[..start];
-        self.is_token = true;
-        self.source = self.source.get(start + 1..)?;
-        Some(Token::Normal(source))
+        object.insert(name.to_string(), value);
       }
     }
   }
+}
 
-  fn handle_token(&mut self) -> Option<Item<'a>> {
-    self.is_token = false;
+#[cfg(test)]
+mod tests {
+  use crate::context::ContextBuilder;
+  use crate::render_to_string;
 
-    let end = self.source.find([' ', '\n']);
+  mod errors {
+    use super::*;
+    use crate::parser::{ParseError, ParseErrorKind};
+    use crate::renderer::Error;
+    use crate::span::Span;
 
-    match end {
-      None => {
-        let source = self.source;
-        self.source = "";
-        Some(Token::Var(source))
-      }
-      Some(end) => match &self.source[..end] {
-        "if" => self.handle_if_token(),
-        "for" => self.handle_for_token(),
-        name => {
-          self.source = &self.source[end..];
-          Some(Token::Var(name))
-        }
-      },
+    #[test]
+    fn malformed_templates() {
+      let context = || ContextBuilder::new().set_bool("a", true).build();
+
+      assert!(matches!(
+        render_to_string(context(), "$if a\nA\n$end"),
+        Err(Error::Parse(ParseError {
+          kind: ParseErrorKind::MissingColon("$if"),
+          ..
+        }))
+      ));
+      assert!(matches!(
+        render_to_string(context(), "$if a:\nA"),
+        Err(Error::Parse(ParseError {
+          kind: ParseErrorKind::UnclosedBlock("$if"),
+          ..
+        }))
+      ));
+      assert!(matches!(
+        render_to_string(context(), "$for a of b: $a"),
+        Err(Error::Parse(ParseError {
+          kind: ParseErrorKind::MalformedFor(_),
+          ..
+        }))
+      ));
+    }
+
+    #[test]
+    fn variable_not_found() {
+      let context = ContextBuilder::new().set_list("list", ["a"]).build();
+      let error = render_to_string(context, "$for a in list:\n  - $b\n$end\n").unwrap_err();
+
+      assert!(matches!(&error, Error::VariableNotFound(name, _) if name == "b"));
+      assert_eq!(
+        Some(Span {
+          line: 2,
+          column: 5,
+          offset: 20
+        }),
+        error.span()
+      );
+      assert_eq!(
+        "variable not found: b\n --> 2:5\n  |\n2 |   - $b\n  |     ^",
+        error.to_string()
+      );
+    }
+
+    #[test]
+    fn no_trailing_newline() {
+      let context = ContextBuilder::new()
+        .set_bool("a", true)
+        .set_list("b", ["1", "2"])
+        .build();
+      let text = "$if a: A\n$for n in b:\n$n\n$end";
+
+      assert_eq!("A\n1\n2\n", render_to_string(context, text).unwrap());
     }
   }
 
-  #[inline]
-  fn hande_block(&mut self, f: impl FnOnce(&'a str) -> Item<'a>) -> Option<Item<'a>> {
-    let line_end = self.source.find('\n').unwrap();
+  mod values {
+    use super::*;
+    use crate::context::Value;
 
-    let is_one_liner = self.source[..line_end].len() > 2;
+    #[test]
+    fn numbers_and_null() {
+      let context = ContextBuilder::new()
+        .set_value("int", -2)
+        .set_value("uint", 7u64)
+        .set_value("float", 0.25)
+        .set_value("null", Value::Null)
+        .set_bool("bool", true)
+        .build();
+      let text = "$int $uint $float [$null] $bool";
 
-    if is_one_liner {
-      let block = &self.source[..line_end];
+      assert_eq!(
+        "-2 7 0.25 [] true",
+        render_to_string(context, text).unwrap()
+      );
+    }
 
-      match block.find("$end") {
-        None => {
-          self.source = &self.source[line_end..];
-          Some(f(&block[2..]))
-        }
-        Some(end) => {
-          let block = &block[..end - 1];
-          self.source = &self.source[end + 5..];
-          Some(f(&block[2..]))
-        }
-      }
-    } else {
-      self.source = &self.source[line_end..];
-      let block_end = self.source.find("$end").unwrap();
-      let block = &self.source[..block_end];
+    #[test]
+    fn paths() {
+      let context = ContextBuilder::new()
+        .set_value(
+          "items",
+          vec![
+            ContextBuilder::new()
+              .set_value("name", "a")
+              .set_bool("done", false)
+              .build_to_value(),
+            ContextBuilder::new()
+              .set_value("name", "b")
+              .set_bool("done", true)
+              .build_to_value(),
+          ],
+        )
+        .set_value(
+          "labels",
+          ContextBuilder::new().set_value("in progress", "WIP"),
+        )
+        .set_value("status", "in progress")
+        .build();
+      let text = "$items[0].name, $items[-1].name. $labels[\"in progress\"] $labels[status]|lower\n$if items[-1].done: done\n$for item in items if !item.done: [$item.name]";
 
-      self.source = &self.source[block_end + 5..];
+      assert_eq!(
+        "a, b. WIP wip\ndone\n[a]",
+        render_to_string(context, text).unwrap()
+      );
+    }
+  }
+
+  mod filters {
+    use super::*;
+    use crate::renderer::Error;
 
-      Some(f(&block[1..]))
+    #[test]
+    fn pipeline() {
+      let context = ContextBuilder::new()
+        .set_value("name", "  ada lovelace ")
+        .set_list("tags", ["b", "a"])
+        .build();
+      let text = "$name|trim|title, $tags|sort|join(\", \")|upper, $missing|default(\"none\")";
+
+      assert_eq!(
+        "Ada Lovelace, A, B, none",
+        render_to_string(context, text).unwrap()
+      );
+    }
+
+    #[test]
+    fn in_conditions() {
+      let context = ContextBuilder::new().set_list("tags", ["a", "b"]).build();
+      let text = "$if tags|length > 1: $tags|first and more";
+
+      assert_eq!("a and more", render_to_string(context, text).unwrap());
+    }
+
+    #[test]
+    fn errors() {
+      let context = || ContextBuilder::new().set_value("count", 1).build();
+
+      assert!(matches!(
+        render_to_string(context(), "$missing|upper"),
+        Err(Error::VariableNotFound(name, _)) if name == "missing"
+      ));
+      assert_eq!(
+        "unknown filter: nope\n --> 1:3\n  |\n1 | a $count|nope\n  |   ^",
+        render_to_string(context(), "a $count|nope")
+          .unwrap_err()
+          .to_string()
+      );
+      assert_eq!(
+        "upper: expected a string but got int\n --> 1:1\n  |\n1 | $count|upper\n  | ^",
+        render_to_string(context(), "$count|upper")
+          .unwrap_err()
+          .to_string()
+      );
     }
   }
 
-  fn handle_if_token(&mut self) -> Option<Item<'a>> {
-    let condition_end = self.source.find(':').unwrap();
-    let condition = &self.source[3..condition_end];
+  mod fors {
+    use super::*;
+    use crate::context::Value;
+    use crate::template::Template;
+
+    #[test]
+    fn for_one_liner() {
+      let context = ContextBuilder::new()
+        .set_list("nums", ["2", "3", "4"])
+        .build();
+      let text = "1 $for n in nums: $n  $end 5\n1 $for n in nums: $n";
+
+      assert_eq!("1 2 3 4 5\n1 234", render_to_string(context, text).unwrap());
+    }
+
+    #[test]
+    fn key_value() {
+      let context = ContextBuilder::new()
+        .set_value(
+          "object",
+          ContextBuilder::new()
+            .set_value("b", "2")
+            .set_value("c", "3")
+            .set_value("a", "1"),
+        )
+        .set_list("list", ["x", "y"])
+        .build();
+      let text = "$for key, value in object: $key=$value;\n$for key in object: $key\n$for i, item in list: $i:$item;";
 
-    self.source = &self.source[condition_end..];
+      assert_eq!(
+        "a=1;b=2;c=3;\nabc\n0:x;1:y;",
+        render_to_string(context, text).unwrap()
+      );
+    }
+
+    #[test]
+    fn else_empty() {
+      let context = |incidents: Vec<Value>| {
+        ContextBuilder::new()
+          .set_value("incidents", incidents)
+          .set_value("tags", ContextBuilder::new())
+          .build()
+      };
+      let text = "$for incident in incidents:\n- $incident\n$else:\nNo open incidents\n$end\n$for tag in tags: $tag $empty: no tags";
+
+      assert_eq!(
+        "No open incidents\nno tags",
+        render_to_string(context(vec![]), text).unwrap()
+      );
+      assert_eq!(
+        "- a\n- b\nno tags",
+        render_to_string(context(vec!["a".into(), "b".into()]), text).unwrap()
+      );
+    }
+
+    #[test]
+    fn not_iterable() {
+      let context = ContextBuilder::new().set_value("count", 1).build();
+      let error = render_to_string(context, "$for n in count: $n").unwrap_err();
+
+      assert_eq!(
+        "can't loop over int: count\n --> 1:1\n  |\n1 | $for n in count: $n\n  | ^",
+        error.to_string()
+      );
+    }
+
+    #[test]
+    fn loop_object() {
+      let context = ContextBuilder::new()
+        .set_list("items", ["a", "b", "c"])
+        .build();
+      let text = "$for item in items: $loop.index/$loop.length $item$if !loop.last: ,";
+
+      assert_eq!(
+        "1/3 a,2/3 b,3/3 c",
+        render_to_string(context.clone(), text).unwrap()
+      );
+
+      let text =
+        "$for item in items: $if loop.first: [$loop.index0 $loop.revindex $loop.revindex0]";
+
+      assert_eq!("[0 3 2]", render_to_string(context, text).unwrap());
+    }
+
+    #[test]
+    fn loop_parent() {
+      let context = ContextBuilder::new()
+        .set_list("rows", ["a", "b"])
+        .set_list("columns", ["1", "2"])
+        .build();
+      let text = "$for row in rows:\n$for column in columns: $loop.parent.index.$loop.index=$row$column \n$end\n$if loop: outside";
+
+      assert_eq!(
+        "1.1=a1 1.2=a2 \n2.1=b1 2.2=b2 \n",
+        render_to_string(context, text).unwrap()
+      );
+    }
+
+    #[test]
+    fn for_nested() {
+      let context = ContextBuilder::new()
+        .set_value(
+          "items",
+          vec![
+            ContextBuilder::new()
+              .set_list("names", ["a", "b"])
+              .build_to_value(),
+            ContextBuilder::new()
+              .set_list("names", ["c"])
+              .build_to_value(),
+          ],
+        )
+        .build();
+      let text = "$for item in items:\n$for name in item.names:\n- $name\n$end\n$end\n";
+
+      assert_eq!("- a\n- b\n- c\n", render_to_string(context, text).unwrap());
+    }
+
+    #[test]
+    fn ranges() {
+      let context = || ContextBuilder::new().set_value("n", 3).build();
+      let render = |text| render_to_string(context(), text);
 
-    self.hande_block(|block| Token::If(condition, false, block, None))
+      assert_eq!("1;2;", render("$for i in 1..n: $i; $end").unwrap());
+      assert_eq!("1;2;3;", render("$for i in 1..=n: $i; $end").unwrap());
+      assert_eq!(
+        "0:0/3;1:1/3;2:2/3;",
+        render("$for index, i in range(n): $index:$i/$loop.length; $end").unwrap()
+      );
+      assert_eq!(
+        "10;7;4;1;",
+        render("$for i in range(10, 0, -3): $i; $end").unwrap()
+      );
+      assert_eq!("empty", render("$for i in 3..1: $i $else: empty").unwrap());
+      assert_eq!(
+        "range step can't be 0\n --> 1:1\n  |\n1 | $for i in range(0, n, 0): $i\n  | ^",
+        render("$for i in range(0, n, 0): $i")
+          .unwrap_err()
+          .to_string()
+      );
+      assert_eq!(
+        "expected an integer but got string\n --> 1:1\n  |\n1 | $for i in 1..\"a\": $i\n  | ^",
+        render("$for i in 1..\"a\": $i").unwrap_err().to_string()
+      );
+      assert_eq!(
+        "range bound 18446744073709551615 is too large, it has to fit in an i64\n --> 1:1\n  |\n1 | $for i in 0..18446744073709551615: $i\n  | ^",
+        render("$for i in 0..18446744073709551615: $i")
+          .unwrap_err()
+          .to_string()
+      );
+    }
+
+    #[test]
+    fn range_length() {
+      use crate::renderer::range_length;
+
+      assert_eq!(5, range_length(0, 5, 1, false));
+      assert_eq!(6, range_length(0, 5, 1, true));
+      assert_eq!(3, range_length(0, 5, 2, true));
+      assert_eq!(0, range_length(5, 0, 1, false));
+      assert_eq!(2, range_length(5, 0, -3, false));
+      assert_eq!(1, range_length(5, 5, -1, true));
+      assert_eq!(usize::MAX, range_length(i64::MIN, i64::MAX, 1, true));
+    }
+
+    #[test]
+    fn break_continue() {
+      let context = || {
+        ContextBuilder::new()
+          .set_list("nums", [1, 2, 3, 4, 5])
+          .set_list("rows", ["a", "b"])
+          .build()
+      };
+
+      assert_eq!(
+        "1;2;",
+        render_to_string(
+          context(),
+          "$for n in nums: $if n == 3: $break $else: $n; $end"
+        )
+        .unwrap()
+      );
+      assert_eq!(
+        "1;2;4;5;",
+        render_to_string(
+          context(),
+          "$for n in nums: $if n == 3: $continue $else: $n; $end"
+        )
+        .unwrap()
+      );
+      assert_eq!(
+        "a1 a2 b1 b2 ",
+        render_to_string(
+          context(),
+          "$for row in rows:\n$for n in nums:\n$if n > 2:\n$break\n$end\n$row$n $end\n$end"
+        )
+        .unwrap()
+      );
+    }
+
+    #[test]
+    fn condition() {
+      let context = || {
+        ContextBuilder::new()
+          .set_list("nums", [1, 2, 3, 4])
+          .set_value(
+            "tags",
+            ContextBuilder::new()
+              .set_bool("a", true)
+              .set_bool("b", false)
+              .set_bool("c", true),
+          )
+          .build()
+      };
+
+      assert_eq!(
+        "3:1/2;4:2/2;",
+        render_to_string(
+          context(),
+          "$for n in nums if n > 2: $n:$loop.index/$loop.length; $end"
+        )
+        .unwrap()
+      );
+      assert_eq!(
+        "a,c",
+        render_to_string(
+          context(),
+          "$for tag, on in tags if on: $tag$if !loop.last: ,"
+        )
+        .unwrap()
+      );
+      assert_eq!(
+        "none",
+        render_to_string(context(), "$for n in nums if n > 4: $n $else: none").unwrap()
+      );
+      assert_eq!(
+        "can't compare int > string\n --> 1:1\n  |\n1 | $for n in nums if n > \"a\": $n\n  | ^",
+        render_to_string(context(), "$for n in nums if n > \"a\": $n")
+          .unwrap_err()
+          .to_string()
+      );
+      assert_eq!(
+        "9,10.",
+        render_to_string(
+          context(),
+          "$for n in 1..=10 if n > 8: $n$if loop.last: . $else: ,"
+        )
+        .unwrap()
+      );
+
+      // errors in the condition are only reported once for each element
+      let diagnostics = Template::check("$for n in nums if n > \"a\": $n", &context());
+      assert_eq!(4, diagnostics.len());
+    }
   }
 
-  fn handle_for_token(&mut self) -> Option<Item<'a>> {
-    let statement_end = self.source.find(':').unwrap();
-    let statement = &self.source[4..statement_end];
-    let (element, elements) = statement.split_once(" in ").unwrap();
+  mod escapes {
+    use super::*;
+
+    #[test]
+    fn dollar_signs() {
+      let context = ContextBuilder::new().set_value("price", "5.00").build();
+      let text = "echo \\$HOME $$PATH\ncosts $5.00, or \\$$price $ $$$price";
+      let rendered = render_to_string(context, text).unwrap();
 
-    self.source = &self.source[statement_end..];
+      assert_eq!("echo $HOME $PATH\ncosts $5.00, or $5.00 $ $5.00", rendered);
+    }
 
-    self.hande_block(|block| Token::For(element, elements, block))
+    #[test]
+    fn backslashes() {
+      let context = ContextBuilder::new().set_value("user", "me").build();
+      let text = "C:\\Users\\\\$user \\\\\\$user";
+
+      assert_eq!(
+        "C:\\Users\\me \\$user",
+        render_to_string(context, text).unwrap()
+      );
+    }
+
+    #[test]
+    fn escaped_in_block() {
+      let context = ContextBuilder::new().set_bool("a", true).build();
+      let text = "$if a: \\$end $$end $else: b";
+
+      assert_eq!("$end $end", render_to_string(context, text).unwrap());
+    }
   }
-}
 
-impl<'a> Iterator for TokenIter<'a> {
-  type Item = Item<'a>;
+  mod comments {
+    use super::*;
 
-  fn next(&mut self) -> Option<Self::Item> {
-    if self.source.is_empty() {
-      return None;
+    #[test]
+    fn comment_lines_are_removed() {
+      let text = "$// comment\nline 1\n$//\nmulti\nline\ncomment\n//$\nline 2\n";
+      let rendered = render_to_string(ContextBuilder::new().build(), text).unwrap();
+
+      assert_eq!("line 1\nline 2\n", rendered);
+    }
+
+    #[test]
+    fn comment_after_text() {
+      let text = "a $// in line comment //$ b\nc $// comment\nd";
+      let rendered = render_to_string(ContextBuilder::new().build(), text).unwrap();
+
+      assert_eq!("a  b\nc \nd", rendered);
+    }
+
+    #[test]
+    fn comment_in_block() {
+      let text = "$if a:\n$// $end $else:\na\n$end\n";
+      let context = ContextBuilder::new().set_bool("a", true).build();
+
+      assert_eq!("a\n", render_to_string(context, text).unwrap());
+    }
+
+    #[test]
+    fn indented_comment_lines() {
+      let context = || ContextBuilder::new().set_bool("a", true).build();
+
+      assert_eq!(
+        "next",
+        render_to_string(context(), "  $// c\nnext").unwrap()
+      );
+      assert_eq!(
+        "  X\n",
+        render_to_string(context(), "$if a:\n  \t$// c\n  X\n$end\n").unwrap()
+      );
+      assert_eq!("a\n", render_to_string(context(), "a\n  $// c").unwrap());
+    }
+  }
+
+  mod ifs {
+    use super::*;
+
+    fn item(exists: bool) -> ContextBuilder {
+      ContextBuilder::new()
+        .set_value("item", "stuff")
+        .set_bool("has_item", exists)
+    }
+
+    #[test]
+    fn indented_blocks() {
+      let context = || {
+        ContextBuilder::new()
+          .set_bool("a", true)
+          .set_bool("b", false)
+          .set_list("items", ["x", "y"])
+          .build()
+      };
+      let render = |text| render_to_string(context(), text).unwrap();
+
+      assert_eq!("a\n  X\nb", render("a\n  $if a:\n  X\n  $end\nb"));
+      assert_eq!(
+        "    x\n    y\n",
+        render("$for i in items:\n  $if a:\n    $i\n  $end\n$end\n")
+      );
+      assert_eq!(
+        "- Z\n",
+        render("  $if b:\n    Y\n  $elif b:\n    Y\n\t$else:\n- Z\n  $end\n")
+      );
+      assert_eq!(
+        "  x\n",
+        render("  $with i = \"x\":\n    $block row:\n  $i\n    $end\n  $end")
+      );
+      assert_eq!("  xy", render("  $for i in items: $i $end\n"));
+    }
+
+    #[test]
+    fn else_multiline() {
+      let text = "line 1\nline 2 $if has_item:\n$item\n$else:\nItem doesn't exist\n$end\nline 3";
+
+      let rendered = render_to_string(item(true).build(), text).unwrap();
+      assert_eq!("line 1\nline 2 stuff\nline 3", rendered);
+
+      let rendered = render_to_string(item(false).build(), text).unwrap();
+      assert_eq!("line 1\nline 2 Item doesn't exist\nline 3", rendered);
+    }
+
+    #[test]
+    fn else_one_liner() {
+      let text = "line 1\nline 2 $if has_item: $item $else: Item doesn't exist\nline 3";
+
+      let rendered = render_to_string(item(true).build(), text).unwrap();
+      assert_eq!("line 1\nline 2 stuff\nline 3", rendered);
+
+      let rendered = render_to_string(item(false).build(), text).unwrap();
+      assert_eq!("line 1\nline 2 Item doesn't exist\nline 3", rendered);
     }
 
-    if !self.is_token {
-      self.token_start()
-    } else {
-      self.handle_token()
+    #[test]
+    fn else_nested() {
+      let text = "$if a:\n$if b: ab $else: a\n$else:\n$if b:\nb\n$else:\nnone\n$end\n$end\n";
+      let context = |a, b| {
+        ContextBuilder::new()
+          .set_bool("a", a)
+          .set_bool("b", b)
+          .build()
+      };
+
+      assert_eq!("ab\n", render_to_string(context(true, true), text).unwrap());
+      assert_eq!("a\n", render_to_string(context(true, false), text).unwrap());
+      assert_eq!("b\n", render_to_string(context(false, true), text).unwrap());
+      assert_eq!(
+        "none\n",
+        render_to_string(context(false, false), text).unwrap()
+      );
+    }
+
+    #[test]
+    fn elif_chain() {
+      let text =
+        "status: $if healthy: healthy $elif degraded: degraded $else if down: down $else: unknown";
+      let context = |status: &str| ContextBuilder::new().set_bool(status, true).build();
+
+      for status in ["healthy", "degraded", "down", "unknown"] {
+        let rendered = render_to_string(context(status), text).unwrap();
+        assert_eq!(format!("status: {status}"), rendered);
+      }
+    }
+
+    #[test]
+    fn elif_multiline() {
+      let text = "$if a:\nA\n$elif b:\n$if c:\nBC\n$end\n$elif c:\nC\n$else:\nnone\n$end\nafter";
+      let context = |a, b, c| {
+        ContextBuilder::new()
+          .set_bool("a", a)
+          .set_bool("b", b)
+          .set_bool("c", c)
+          .build()
+      };
+
+      assert_eq!(
+        "A\nafter",
+        render_to_string(context(true, true, true), text).unwrap()
+      );
+      assert_eq!(
+        "BC\nafter",
+        render_to_string(context(false, true, true), text).unwrap()
+      );
+      assert_eq!(
+        "after",
+        render_to_string(context(false, true, false), text).unwrap()
+      );
+      assert_eq!(
+        "C\nafter",
+        render_to_string(context(false, false, true), text).unwrap()
+      );
+      assert_eq!(
+        "none\nafter",
+        render_to_string(context(false, false, false), text).unwrap()
+      );
+    }
+
+    #[test]
+    fn not() {
+      let context = ContextBuilder::new()
+        .set_bool("true", true)
+        .set_value("nested", ContextBuilder::new().set_bool("false", false))
+        .build();
+
+      let text = "$if !true: a $else: b\n$if !nested.false: c\n$if !!true: d\n$if !missing: e";
+      let rendered = render_to_string(context, text).unwrap();
+
+      assert_eq!("b\nc\nd\ne", rendered);
+    }
+
+    #[test]
+    fn truthy() {
+      let context = ContextBuilder::new()
+        .set_value("item", "stuff")
+        .set_value("empty", "")
+        .set_list("items", ["a"])
+        .set_value("count", 0)
+        .build();
+      let text = "$if item: $item\n$if empty: empty\n$if items: items\n$if !count: none";
+
+      assert_eq!(
+        "stuff\n\nitems\nnone",
+        render_to_string(context, text).unwrap()
+      );
+    }
+
+    #[test]
+    fn expressions() {
+      let text = "$if status == \"failed\": failed $elif count > 0 and not archived: $count left";
+      let context = |status: &str, count: i64| {
+        ContextBuilder::new()
+          .set_value("status", status)
+          .set_value("count", count)
+          .set_bool("archived", false)
+          .build()
+      };
+
+      assert_eq!(
+        "failed",
+        render_to_string(context("failed", 1), text).unwrap()
+      );
+      assert_eq!("2 left", render_to_string(context("ok", 2), text).unwrap());
+      assert_eq!("", render_to_string(context("ok", 0), text).unwrap());
+    }
+
+    #[test]
+    fn type_error() {
+      let context = ContextBuilder::new().set_value("count", "3").build();
+      let error = render_to_string(context, "a\n$if count > 0: b").unwrap_err();
+
+      assert_eq!(
+        "can't compare string > int\n --> 2:1\n  |\n2 | $if count > 0: b\n  | ^",
+        error.to_string()
+      );
+    }
+
+    #[test]
+    fn not_elif() {
+      let text = "$if a: a $elif !b: not b $else: b";
+      let context = |b| {
+        ContextBuilder::new()
+          .set_bool("a", false)
+          .set_bool("b", b)
+          .build()
+      };
+
+      assert_eq!("not b", render_to_string(context(false), text).unwrap());
+      assert_eq!("b", render_to_string(context(true), text).unwrap());
+    }
+  }
+
+  mod macros {
+    use super::*;
+    use crate::context::Value;
+    use crate::renderer::Error;
+
+    const BADGE: &str = "$macro badge(label, color = \"grey\"): [$label:$color]\n";
+
+    #[test]
+    fn call() {
+      let context = ContextBuilder::new()
+        .set_value("status", "ok")
+        .set_list("items", ["a", "b"])
+        .build();
+      let text = format!(
+        "{BADGE}$badge(\"ok\", \"green\") $badge(status)\n$for item in items: $badge(item)|upper;"
+      );
+
+      assert_eq!(
+        "[ok:green] [ok:grey]\n[A:GREY];[B:GREY];",
+        render_to_string(context, text).unwrap()
+      );
+    }
+
+    #[test]
+    fn local_scope() {
+      let context = ContextBuilder::new()
+        .set_value("site", "docs")
+        .set_list("items", ["a"])
+        .build();
+      let text = "$for item in items:\n$row()\n$end\n$macro row(): $site $item\n";
+
+      assert_eq!(
+        "variable not found: item\n --> 4:21\n  |\n4 | $macro row(): $site $item\n  |                     ^",
+        render_to_string(context, text).unwrap_err().to_string()
+      );
+    }
+
+    #[test]
+    fn arguments() {
+      let context = || ContextBuilder::new().build();
+
+      assert!(matches!(
+        render_to_string(context(), format!("{BADGE}$badge()")),
+        Err(Error::Eval(error, _)) if error.to_string() == "badge is missing its argument label"
+      ));
+      assert!(matches!(
+        render_to_string(context(), format!("{BADGE}$badge(1, 2, 3)")),
+        Err(Error::Eval(error, _)) if error.to_string() == "badge takes at most 2 arguments but got 3"
+      ));
+    }
+
+    #[test]
+    fn recursive() {
+      let node = |name, children: Vec<Value>| {
+        ContextBuilder::new()
+          .set_value("name", name)
+          .set_value("children", children)
+      };
+      let tree = node(
+        "a",
+        vec![
+          node("b", vec![node("c", vec![]).into()]).into(),
+          node("d", vec![]).into(),
+        ],
+      );
+      let context = ContextBuilder::new().set_value("tree", tree).build();
+      let text = "$macro walk(n): $n.name{$for child in n.children: $walk(child)$end}\n$walk(tree)";
+
+      assert_eq!("a{b{c{}}d{}}", render_to_string(context, text).unwrap());
+    }
+
+    #[test]
+    fn too_deep() {
+      let render = |text| {
+        render_to_string(ContextBuilder::new().build(), text)
+          .unwrap_err()
+          .to_string()
+      };
+
+      assert_eq!(
+        "$f() nested more than 100 levels deep\n --> 1:14\n  |\n1 | $macro f(): x$f()\n  |              ^",
+        render("$macro f(): x$f()\n$f()")
+      );
+      assert_eq!(
+        "$b() nested more than 100 levels deep\n --> 1:13\n  |\n1 | $macro a(): $b()\n  |             ^",
+        render("$macro a(): $b()\n$macro b(): $a()\n$a()")
+      );
+    }
+  }
+
+  mod assignments {
+    use super::*;
+
+    #[test]
+    fn set() {
+      let context = ContextBuilder::new()
+        .set_value("page", ContextBuilder::new().set_value("title", "docs"))
+        .set_list("items", ["a", "b"])
+        .build();
+      let text = "$set title = page.title|upper\n$if title:\n$set found = true\n$end\n$for item in items:\n$set last = item\n$end\n$title $found $last|default(\"none\")";
+
+      assert_eq!("DOCS true none", render_to_string(context, text).unwrap());
+    }
+
+    #[test]
+    fn with() {
+      let context = ContextBuilder::new()
+        .set_value(
+          "a",
+          ContextBuilder::new().set_value("b", ContextBuilder::new().set_value("c", 1)),
+        )
+        .set_value("d", 2)
+        .build();
+      let text = "$with c = a.b.c, e = c == 1:\n$set d = 3\n$c $d $e\n$end\n$d $c|default(0)";
+
+      assert_eq!("1 3 true\n2 0", render_to_string(context, text).unwrap());
     }
   }
 }
@@ -0,0 +1,161 @@
+use std::fmt::{Display, Formatter};
+
+/// A position in a template, `line` and `column` start at 1
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Hash)]
+pub struct Span {
+  pub line: usize,
+  pub column: usize,
+  /// byte offset into the template
+  pub offset: usize,
+}
+
+impl Span {
+  pub fn new(source: &str, offset: usize) -> Self {
+    let before = &source[..offset];
+    let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+
+    Self {
+      line: before.matches('\n').count() + 1,
+      column: before[line_start..].chars().count() + 1,
+      offset,
+    }
+  }
+}
+
+impl Display for Span {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{}:{}", self.line, self.column)
+  }
+}
+
+/// Finds the [Span] of byte offsets without rescanning the whole template every time
+#[derive(Debug, Clone)]
+pub(crate) struct Lines<'a> {
+  source: &'a str,
+  /// byte offset of the start of every line
+  starts: Vec<usize>,
+}
+
+impl<'a> Lines<'a> {
+  pub(crate) fn new(source: &'a str) -> Self {
+    let starts = std::iter::once(0)
+      .chain(source.match_indices('\n').map(|(index, _)| index + 1))
+      .collect();
+
+    Self { source, starts }
+  }
+
+  pub(crate) fn span(&self, offset: usize) -> Span {
+    let line = self.starts.partition_point(|start| *start <= offset);
+    let line_start = self.starts[line - 1];
+
+    Span {
+      line,
+      column: self.source[line_start..offset].chars().count() + 1,
+      offset,
+    }
+  }
+}
+
+/// A [Span] along with the line of the template it points into,
+/// displays like rustc does
+///
+/// ```text
+///  --> 2:4
+///   |
+/// 2 | a $if b
+///   |   ^
+/// ```
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Snippet {
+  pub span: Span,
+  pub source_line: String,
+  /// name of the template it's in, for templates from a [TemplateLoader](crate::loader::TemplateLoader)
+  pub file: Option<String>,
+}
+
+impl Snippet {
+  pub fn new(source: &str, span: Span) -> Self {
+    let line_start = source[..span.offset]
+      .rfind('\n')
+      .map_or(0, |index| index + 1);
+    let line_end = source[span.offset..]
+      .find('\n')
+      .map_or(source.len(), |index| span.offset + index);
+
+    Self {
+      span,
+      source_line: source[line_start..line_end]
+        .trim_end_matches('\r')
+        .to_string(),
+      file: None,
+    }
+  }
+
+  pub fn with_file(mut self, file: Option<&str>) -> Self {
+    self.file = file.map(str::to_string);
+    self
+  }
+}
+
+impl Display for Snippet {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    let line = self.span.line.to_string();
+    let gutter = " ".repeat(line.len());
+    let indent = self
+      .source_line
+      .chars()
+      .take(self.span.column.saturating_sub(1))
+      .map(|c| if c == '\t' { '\t' } else { ' ' })
+      .collect::<String>();
+
+    match &self.file {
+      Some(file) => writeln!(f, "{gutter}--> {file}:{}", self.span)?,
+      None => writeln!(f, "{gutter}--> {}", self.span)?,
+    }
+
+    writeln!(f, "{gutter} |")?;
+    writeln!(f, "{line} | {}", self.source_line)?;
+    write!(f, "{gutter} | {indent}^")
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::span::{Lines, Snippet, Span};
+
+  #[test]
+  fn span() {
+    let source = "a\nbc $if\n\n  é$d";
+    let lines = Lines::new(source);
+
+    for (offset, _) in source.char_indices() {
+      assert_eq!(Span::new(source, offset), lines.span(offset));
+    }
+
+    assert_eq!(
+      Span {
+        line: 4,
+        column: 4,
+        offset: 14
+      },
+      lines.span(14)
+    );
+  }
+
+  #[test]
+  fn snippet() {
+    let source = "line 1\n\tline 2 $if a\nline 3";
+    let snippet = Snippet::new(source, Span::new(source, 15));
+
+    assert_eq!("\tline 2 $if a", snippet.source_line);
+    assert_eq!(
+      " --> 2:9\n  |\n2 | \tline 2 $if a\n  | \t       ^",
+      snippet.to_string()
+    );
+    assert_eq!(
+      " --> header.txt:2:9\n  |\n2 | \tline 2 $if a\n  | \t       ^",
+      snippet.with_file(Some("header.txt")).to_string()
+    );
+  }
+}
@@ -0,0 +1,229 @@
+use std::io::Write;
+use std::str::FromStr;
+
+use crate::context::Context;
+use crate::diagnostic::Diagnostic;
+use crate::environment::Environment;
+use crate::parser::{parse, Node, ParseError};
+use crate::renderer::Error;
+
+/// A parsed template, parse it once and render it as many times as needed,
+/// render it with an [Environment] to use custom filters and functions
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+  /// name it was loaded by, see [Environment::get_template]
+  name: Option<String>,
+  source: String,
+  nodes: Vec<Node>,
+  /// conditions have to be bools instead of any truthy value
+  strict: bool,
+}
+
+impl Template {
+  pub fn parse<S>(source: S) -> Result<Self, ParseError>
+  where
+    S: AsRef<str>,
+  {
+    let source = source.as_ref();
+
+    Ok(Self {
+      name: None,
+      nodes: parse(source)?,
+      source: source.to_string(),
+      strict: false,
+    })
+  }
+
+  /// Requires conditions to be real bools, any other value is an error instead of being truthy or not,
+  /// see [Value::is_truthy](crate::context::Value::is_truthy), it also applies to every template
+  /// it includes or extends and to the macros it calls, see [Environment::set_strict] for templates
+  /// rendered by name
+  pub fn strict(mut self, strict: bool) -> Self {
+    self.strict = strict;
+    self
+  }
+
+  pub fn is_strict(&self) -> bool {
+    self.strict
+  }
+
+  pub fn name(&self) -> Option<&str> {
+    self.name.as_deref()
+  }
+
+  pub(crate) fn named(mut self, name: &str) -> Self {
+    self.name = Some(name.to_string());
+    self
+  }
+
+  pub(crate) fn from_nodes(source: &str, nodes: Vec<Node>) -> Self {
+    Self {
+      name: None,
+      source: source.to_string(),
+      nodes,
+      strict: false,
+    }
+  }
+
+  /// Checks a template for every error it has instead of stopping at the first one,
+  /// it's parsed as far as possible and then rendered against `context` without any output,
+  /// see [Environment::check] to check it with custom filters and functions
+  pub fn check<S>(source: S, context: &Context) -> Vec<Diagnostic>
+  where
+    S: AsRef<str>,
+  {
+    Environment::new().check(source, context)
+  }
+
+  /// Renders the template against `context` without any output,
+  /// collecting every error instead of stopping at the first one
+  pub fn validate(&self, context: &Context) -> Vec<Diagnostic> {
+    Environment::new().validate(self, context)
+  }
+
+  pub fn source(&self) -> &str {
+    &self.source
+  }
+
+  pub fn nodes(&self) -> &[Node] {
+    &self.nodes
+  }
+
+  pub fn render<W>(&self, context: &Context, writer: W) -> Result<(), Error>
+  where
+    W: Write,
+  {
+    Environment::new().render(self, context, writer)
+  }
+
+  pub fn render_to_string(&self, context: &Context) -> Result<String, Error> {
+    Environment::new().render_to_string(self, context)
+  }
+}
+
+impl FromStr for Template {
+  type Err = ParseError;
+
+  fn from_str(source: &str) -> Result<Self, Self::Err> {
+    Self::parse(source)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::context::{ContextBuilder, SetValue};
+  use crate::diagnostic::Severity;
+  use crate::parser::ParseErrorKind;
+  use crate::template::Template;
+
+  #[test]
+  fn render_many_times() {
+    let template = Template::parse("$for n in nums: [$n] $end$if done: done").unwrap();
+    let mut context = ContextBuilder::new()
+      .set_list("nums", ["1", "2"])
+      .set_bool("done", false)
+      .build();
+
+    assert_eq!("[1][2]", template.render_to_string(&context).unwrap());
+    assert_eq!("[1][2]", template.render_to_string(&context).unwrap());
+
+    context.set_list("nums", ["3"]);
+    context.set_bool("done", true);
+
+    assert_eq!("[3]done", template.render_to_string(&context).unwrap());
+  }
+
+  #[test]
+  fn render_to_writer() {
+    let template: Template = "Hello $name".parse().unwrap();
+    let context = ContextBuilder::new().set_value("name", "World").build();
+    let mut buf = Vec::new();
+
+    template.render(&context, &mut buf).unwrap();
+
+    assert_eq!(b"Hello World", buf.as_slice());
+  }
+
+  #[test]
+  fn parse_error() {
+    assert_eq!(
+      ParseErrorKind::UnclosedBlock("$if"),
+      Template::parse("$if a:\n").unwrap_err().kind
+    );
+  }
+
+  #[test]
+  fn check() {
+    let context = ContextBuilder::new()
+      .set_bool("show", true)
+      .set_list("items", ["a"])
+      .build();
+    let source = "$if show\nHello $nam\n$end\n$for item of items: $item\n$for i in items: $i $missing\n$end\n$if flag: $else: $other";
+    let diagnostics = Template::check(source, &context);
+
+    let found = diagnostics
+      .iter()
+      .map(|diagnostic| {
+        let span = diagnostic.span();
+        (
+          diagnostic.severity,
+          span.line,
+          span.column,
+          diagnostic.message.as_str(),
+        )
+      })
+      .collect::<Vec<_>>();
+
+    assert_eq!(
+      vec![
+        (Severity::Error, 1, 1, "$if is missing its `:`"),
+        (Severity::Error, 2, 7, "variable not found: nam"),
+        (
+          Severity::Error,
+          4,
+          1,
+          "malformed $for, expected `$for [key,] name in variable:` but got `$for item of items:`"
+        ),
+        (Severity::Error, 5, 21, "variable not found: missing"),
+        (Severity::Error, 6, 1, "unexpected $end"),
+        (
+          Severity::Warning,
+          7,
+          1,
+          "variable not found, treated as null: flag"
+        ),
+        (Severity::Error, 7, 18, "variable not found: other"),
+      ],
+      found
+    );
+  }
+
+  #[test]
+  fn strict() {
+    let context = ContextBuilder::new()
+      .set_list("items", ["a"])
+      .set_bool("show", true)
+      .build();
+    let template = Template::parse("$if items: $items $else: none\n$if show: shown").unwrap();
+
+    assert_eq!("[a]\nshown", template.render_to_string(&context).unwrap());
+
+    let template = template.strict(true);
+    let diagnostics = template.validate(&context);
+
+    assert!(template.render_to_string(&context).is_err());
+    assert_eq!(1, diagnostics.len());
+    assert_eq!("expected a bool but got list", diagnostics[0].message);
+  }
+
+  #[test]
+  fn check_valid() {
+    let context = ContextBuilder::new().set_bool("a", true).build();
+
+    assert!(Template::check("$if a: yes", &context).is_empty());
+    assert!(Template::parse("$if a: yes")
+      .unwrap()
+      .validate(&context)
+      .is_empty());
+  }
+}